@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS systems (
+    symbol              text,
+    sector_symbol       text,
+    type                text,
+    x                   int,
+    y                   int,
+    factions            text[],
+    controlling_faction text
+);
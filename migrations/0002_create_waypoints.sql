@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS waypoints (
+    symbol              text,
+    type                text,
+    system_symbol       text,
+    x                   int,
+    y                   int,
+    is_marketplace      boolean,
+    is_shipyard         boolean
+);
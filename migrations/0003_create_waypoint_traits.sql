@@ -0,0 +1,9 @@
+-- The bulk systems listing carries no traits, so this table is filled
+-- whenever full waypoint data is fetched.
+CREATE TABLE IF NOT EXISTS waypoint_traits (
+    waypoint_symbol     text,
+    trait_symbol        text,
+    name                text,
+    description         text,
+    PRIMARY KEY (waypoint_symbol, trait_symbol)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS contracts (
+    id                  text PRIMARY KEY,
+    accepted            boolean,
+    fulfilled           boolean,
+    deadline            timestamptz
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS system_bookmarks (
+    system_symbol       text PRIMARY KEY,
+    label               text,
+    created_at          timestamptz DEFAULT NOW()
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS game_events (
+    kind                text,
+    title               text,
+    body                text,
+    first_seen          timestamptz DEFAULT NOW(),
+    PRIMARY KEY (kind, title)
+);
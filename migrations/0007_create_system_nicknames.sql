@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS system_nicknames (
+    system_symbol       text PRIMARY KEY,
+    nickname            text UNIQUE
+);
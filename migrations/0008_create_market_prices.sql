@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS market_prices (
+    waypoint_symbol     text,
+    trade_symbol        text,
+    purchase_price      int,
+    sell_price          int,
+    trade_volume        int,
+    supply              text,
+    observed_at         timestamptz DEFAULT NOW(),
+    PRIMARY KEY (waypoint_symbol, trade_symbol)
+);
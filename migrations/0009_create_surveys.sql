@@ -0,0 +1,8 @@
+-- size is kept so a survey can be sent back to the API when extracting with it.
+CREATE TABLE IF NOT EXISTS surveys (
+    signature           text PRIMARY KEY,
+    waypoint_symbol     text,
+    deposits            text[],
+    expiration          timestamptz,
+    size                text
+);
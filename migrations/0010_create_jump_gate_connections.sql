@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS jump_gate_connections (
+    from_system         text,
+    to_system           text,
+    distance            int,
+    PRIMARY KEY (from_system, to_system)
+);
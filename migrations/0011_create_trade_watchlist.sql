@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS trade_watchlist (
+    trade_symbol        text PRIMARY KEY,
+    -- Alert when a market pays at least this much for the good.
+    min_sell_alert      bigint,
+    -- Alert when a market sells the good for at most this much.
+    max_buy_alert       bigint
+);
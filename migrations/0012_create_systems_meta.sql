@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS systems_meta (
+    key                 text PRIMARY KEY,
+    value               text
+);
+
+-- Systems and waypoints are upserted by symbol, so each symbol may only appear once.
+DELETE FROM systems a USING systems b WHERE a.ctid < b.ctid AND a.symbol = b.symbol;
+CREATE UNIQUE INDEX IF NOT EXISTS systems_symbol_key ON systems (symbol);
+
+DELETE FROM waypoints a USING waypoints b WHERE a.ctid < b.ctid AND a.symbol = b.symbol;
+CREATE UNIQUE INDEX IF NOT EXISTS waypoints_symbol_key ON waypoints (symbol);
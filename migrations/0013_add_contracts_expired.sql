@@ -0,0 +1,2 @@
+-- Contracts the API no longer returns are kept, but marked expired.
+ALTER TABLE contracts ADD COLUMN IF NOT EXISTS expired boolean NOT NULL DEFAULT false;
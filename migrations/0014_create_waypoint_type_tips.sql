@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS waypoint_type_tips (
+    waypoint_type       text PRIMARY KEY,
+    tip                 text
+);
+
+INSERT INTO waypoint_type_tips(waypoint_type, tip) VALUES
+    ('PLANET', 'Planets often host marketplaces and shipyards. Check the traits for what is traded here.'),
+    ('GAS_GIANT', 'Gas giants can be siphoned for hydrocarbons and liquid gases by ships with the right mounts.'),
+    ('MOON', 'Moons are usually close to their planets, so hopping between them costs little fuel.'),
+    ('ORBITAL_STATION', 'Orbital stations are common trade hubs. Compare their prices with nearby planets.'),
+    ('JUMP_GATE', 'Jump gates connect to other systems. Build the jump gate graph to plan routes through them.'),
+    ('ASTEROID_FIELD', 'Survey before extracting to significantly increase yield. Surveys expire after 1 hour.'),
+    ('NEBULA', 'Nebulae can interfere with ship systems. Expect little to trade here.'),
+    ('DEBRIS_FIELD', 'Debris fields can hold salvage, but rarely have a marketplace.'),
+    ('GRAVITY_WELL', 'Gravity wells make travel in and out costly. Check fuel before flying here.')
+ON CONFLICT (waypoint_type) DO NOTHING;
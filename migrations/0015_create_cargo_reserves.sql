@@ -0,0 +1,5 @@
+-- Units of each good that selling must leave in the hold.
+CREATE TABLE IF NOT EXISTS cargo_reserves (
+    trade_symbol        text PRIMARY KEY,
+    min_units_reserved  int NOT NULL
+);
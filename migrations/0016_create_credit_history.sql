@@ -0,0 +1,5 @@
+-- Credits seen each time the status bar is shown, to report the change over the day.
+CREATE TABLE IF NOT EXISTS credit_history (
+    observed_at         timestamptz PRIMARY KEY DEFAULT NOW(),
+    credits             bigint NOT NULL
+);
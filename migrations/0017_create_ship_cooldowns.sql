@@ -0,0 +1,5 @@
+-- When each ship's last survey or extraction cooldown ends.
+CREATE TABLE IF NOT EXISTS ship_cooldowns (
+    ship_symbol         text PRIMARY KEY,
+    cooldown_expiry     timestamptz NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Systems and waypoints as they were before the last server reset, to compare against the new universe.
+CREATE TABLE IF NOT EXISTS systems_archive (LIKE systems);
+CREATE TABLE IF NOT EXISTS waypoints_archive (LIKE waypoints);
@@ -0,0 +1,8 @@
+-- Systems within a few jumps of each other, filled by PopulateDistanceCache from jump_gate_connections.
+CREATE TABLE IF NOT EXISTS system_distance_cache (
+    from_symbol         text,
+    to_symbol           text,
+    euclidean_distance  float8,
+    jump_hops           int NOT NULL,
+    PRIMARY KEY (from_symbol, to_symbol)
+);
@@ -0,0 +1,310 @@
+//! Tasks that run alongside the interactive menu.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use spacedust::models::ShipNavStatus;
+use tokio::sync::watch;
+use tracing::Instrument;
+
+use crate::context::Context;
+use crate::st_util::{self, describe_api_error};
+use crate::{record_cooldown, record_market_prices, waypoint_is_marketplace};
+
+/// Default interval between market refreshes, unless overridden by `MARKET_REFRESH_SECS`.
+pub const DEFAULT_MARKET_REFRESH_SECS: u64 = 300;
+
+/// Default interval between fleet-wide checks of critical goods, unless overridden by `CRITICAL_RESOURCE_POLL_SECS`.
+pub const DEFAULT_CRITICAL_RESOURCE_POLL_SECS: u64 = 300;
+
+/// Default minutes before the last survey at a waypoint expires that a new one is made, unless overridden by `SURVEY_WARN_MINS`.
+pub const DEFAULT_SURVEY_WARN_MINS: i32 = 15;
+
+/// How often stored surveys are checked for upcoming expiry.
+const SURVEY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the database connection is checked.
+const DATABASE_HEALTH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a database check may take before the database counts as unreachable.
+const DATABASE_HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Goods the fleet should always carry, with the total units below which a warning is shown.
+const CRITICAL_GOODS: [(&str, i32); 2] = [("FUEL", 100), ("ANTIMATTER", 10)];
+
+/// Number of markets suggested when a critical good runs low.
+const CRITICAL_GOOD_MARKET_SUGGESTIONS: i64 = 3;
+
+/// Whether the database is reachable, as last checked by the health task or by a task whose query failed.
+#[derive(Clone)]
+pub struct DatabaseHealth {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl DatabaseHealth {
+    /// Runs `SELECT 1` and publishes whether it succeeded, logging when that changes. Returns whether it succeeded.
+    async fn check(&self, ctx: &Context) -> bool {
+        let error = match tokio::time::timeout(DATABASE_HEALTH_TIMEOUT, sqlx::query("SELECT 1").execute(&ctx.db_pool)).await {
+            Ok(Ok(_)) => None,
+            Ok(Err(err)) => Some(err.to_string()),
+            Err(_) => Some(format!("timed out after {}s", DATABASE_HEALTH_TIMEOUT.as_secs())),
+        };
+        let healthy = error.is_none();
+        let was_healthy = self.sender.send_replace(healthy);
+        match error {
+            Some(error) if was_healthy => tracing::warn!(%error, "database unreachable, pausing background tasks"),
+            None if !was_healthy => tracing::info!("database reachable again, resuming background tasks"),
+            _ => {}
+        }
+        healthy
+    }
+
+    /// Waits until the database is reported reachable.
+    async fn wait_until_reachable(&self) {
+        // The sender is held by `self`, so this can't fail.
+        let _ = self.sender.subscribe().wait_for(|healthy| *healthy).await;
+    }
+}
+
+/// Checks the database every `DATABASE_HEALTH_INTERVAL`, publishing whether it is reachable.
+/// The pool replaces connections that fail their check on acquire, so once the server is back
+/// the next check reconnects and the other tasks resume.
+pub fn start_database_health_task(ctx: &Context) -> DatabaseHealth {
+    let health = DatabaseHealth { sender: Arc::new(watch::channel(true).0) };
+    let ctx = ctx.clone();
+    let task_health = health.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(DATABASE_HEALTH_INTERVAL);
+        loop {
+            interval.tick().await;
+            task_health.check(&ctx).await;
+        }
+    });
+    health
+}
+
+/// Runs `run` once the database is reachable. If it fails and the database turns out to be unreachable,
+/// waits for the database to come back and runs it again. Other errors are logged and give `None`.
+async fn run_when_reachable<T, F>(ctx: &Context, health: &DatabaseHealth, what: &str, run: impl Fn() -> F) -> Option<T>
+where
+    F: Future<Output = Result<T, sqlx::Error>>,
+{
+    loop {
+        health.wait_until_reachable().await;
+        match run().await {
+            Ok(value) => return Some(value),
+            Err(err) => {
+                tracing::error!(error = ?err, "{what} failed");
+                if health.check(ctx).await {
+                    return None;
+                }
+                tracing::info!("retrying {what} once the database is reachable");
+            }
+        }
+    }
+}
+
+/// Records prices at every marketplace where one of our ships is docked.
+/// Returns the number of markets refreshed and the number of API calls that failed.
+///
+/// # Errors
+/// Propogates any database error
+async fn refresh_docked_markets(ctx: &Context) -> Result<(usize, usize), sqlx::Error> {
+    let ships = match st_util::list_ships(ctx).await {
+        Ok(ships) => ships,
+        Err(err) => {
+            tracing::error!(error = %describe_api_error(&err), "listing ships failed");
+            return Ok((0, 1));
+        }
+    };
+
+    let mut refreshed = 0;
+    let mut failed = 0;
+    let mut visited = HashSet::new();
+    for ship in ships.iter().filter(|ship| ship.nav.status == ShipNavStatus::Docked) {
+        if !visited.insert(ship.nav.waypoint_symbol.clone()) {
+            continue;
+        }
+        if waypoint_is_marketplace(ctx, &ship.nav.waypoint_symbol).await? != Some(true) {
+            continue;
+        }
+        match st_util::get_market_for_ship(ctx, ship).await {
+            Ok(market) => {
+                record_market_prices(ctx, &market).await?;
+                refreshed += 1;
+            }
+            Err(err) => {
+                tracing::warn!(waypoint = %ship.nav.waypoint_symbol, error = %err, "market refresh failed");
+                failed += 1;
+            }
+        }
+    }
+    Ok((refreshed, failed))
+}
+
+/// Refreshes the prices at markets with a docked ship every `interval`, while `health` reports the database reachable.
+pub fn start_market_refresh_task(ctx: &Context, interval: Duration, health: DatabaseHealth) -> tokio::task::JoinHandle<()> {
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let span = tracing::info_span!("market_refresh", refreshed = tracing::field::Empty, failed = tracing::field::Empty);
+            async {
+                if let Some((refreshed, failed)) = run_when_reachable(&ctx, &health, "market refresh", || refresh_docked_markets(&ctx)).await {
+                    let span = tracing::Span::current();
+                    span.record("refreshed", refreshed);
+                    span.record("failed", failed);
+                    tracing::debug!(refreshed, failed, "market refresh finished");
+                }
+            }
+            .instrument(span)
+            .await;
+        }
+    })
+}
+
+/// Units of each critical good held across the fleet's cargo holds, or `None` if the ships couldn't be listed.
+async fn critical_goods_held(ctx: &Context) -> Option<HashMap<&'static str, i32>> {
+    let ships = match st_util::list_ships(ctx).await {
+        Ok(ships) => ships,
+        Err(err) => {
+            tracing::error!(error = %describe_api_error(&err), "listing ships failed");
+            return None;
+        }
+    };
+    let mut held: HashMap<&'static str, i32> = CRITICAL_GOODS.iter().map(|(good, _)| (*good, 0)).collect();
+    for item in ships.iter().flat_map(|ship| &ship.cargo.inventory) {
+        if let Some(units) = held.get_mut(item.symbol.as_str()) {
+            *units += item.units;
+        }
+    }
+    Some(held)
+}
+
+/// Prints a warning that the fleet is low on `good`, with the cheapest recorded markets selling it.
+///
+/// # Errors
+/// Propogates any database error from finding the markets
+async fn warn_critical_good(ctx: &Context, good: &str, units: i32, threshold: i32) -> Result<(), sqlx::Error> {
+    println!("\n⚠️  LOW {good}: the fleet holds {units} units, below the threshold of {threshold}");
+    let markets: Vec<(String, i32)> = sqlx::query_as(
+        "SELECT waypoint_symbol, purchase_price FROM market_prices WHERE trade_symbol = $1 ORDER BY purchase_price LIMIT $2")
+        .bind(good)
+        .bind(CRITICAL_GOOD_MARKET_SUGGESTIONS)
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    if markets.is_empty() {
+        println!("   No recorded market sells {good}");
+    }
+    for (waypoint_symbol, purchase_price) in markets {
+        println!("   Buy at {waypoint_symbol} for {purchase_price}");
+    }
+    Ok(())
+}
+
+/// Checks the fleet's critical goods every `interval`, warning once each time a good drops below its threshold.
+pub fn start_critical_resource_task(ctx: &Context, interval: Duration, health: DatabaseHealth) -> tokio::task::JoinHandle<()> {
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        let mut warned = HashSet::new();
+        loop {
+            interval.tick().await;
+            let Some(held) = critical_goods_held(&ctx).await else {
+                continue;
+            };
+            for (good, threshold) in CRITICAL_GOODS {
+                let units = held.get(good).copied().unwrap_or_default();
+                if units >= threshold {
+                    warned.remove(good);
+                } else if !warned.contains(good) {
+                    let warning = run_when_reachable(&ctx, &health, "finding markets for critical good", || {
+                        warn_critical_good(&ctx, good, units, threshold)
+                    }).await;
+                    if warning.is_some() {
+                        warned.insert(good);
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Waypoints where every stored survey expires within `warn_mins` minutes.
+///
+/// # Errors
+/// Propogates any database error
+async fn waypoints_with_expiring_surveys(ctx: &Context, warn_mins: i32) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT waypoint_symbol FROM surveys WHERE expiration > NOW()
+        GROUP BY waypoint_symbol HAVING MAX(expiration) <= NOW() + make_interval(mins => $1)")
+        .bind(warn_mins)
+        .fetch_all(&ctx.db_pool)
+        .await
+}
+
+/// Surveys again at each waypoint whose surveys are about to run out, using a ship already in orbit there with a
+/// surveyor mount and no cooldown. Ships are never moved or undocked for this.
+///
+/// # Errors
+/// Propogates any database error from finding the waypoints or ships, which happens before any survey is made.
+/// Database errors after a survey are only logged, so a retry of this run never surveys twice.
+async fn refresh_expiring_surveys(ctx: &Context, warn_mins: i32) -> Result<(), sqlx::Error> {
+    let waypoints = waypoints_with_expiring_surveys(ctx, warn_mins).await?;
+    if waypoints.is_empty() {
+        return Ok(());
+    }
+    let ships = match st_util::list_ships(ctx).await {
+        Ok(ships) => ships,
+        Err(err) => {
+            tracing::error!(error = %describe_api_error(&err), "listing ships failed");
+            return Ok(());
+        }
+    };
+    let ready: Vec<String> = sqlx::query_scalar(
+        "SELECT s FROM unnest($1::text[]) s WHERE NOT EXISTS (SELECT FROM ship_cooldowns c WHERE c.ship_symbol = s AND c.cooldown_expiry > NOW())")
+        .bind(ships.iter().map(|ship| ship.symbol.as_str()).collect::<Vec<_>>())
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    for waypoint_symbol in waypoints {
+        let surveyor = ships.iter().find(|ship| {
+            ship.nav.waypoint_symbol == waypoint_symbol
+                && ship.nav.status == ShipNavStatus::InOrbit
+                && ready.contains(&ship.symbol)
+                && ship.mounts.iter().any(|mount| st_util::api_name(&mount.symbol).starts_with("MOUNT_SURVEYOR"))
+        });
+        let Some(surveyor) = surveyor else {
+            tracing::debug!(waypoint = %waypoint_symbol, "surveys expiring but no surveyor available");
+            continue;
+        };
+        match spacedust::apis::fleet_api::create_survey(&ctx.configuration, &surveyor.symbol, 0).await {
+            Ok(res) => {
+                if let Err(err) = st_util::store_surveys(ctx, &res.data.surveys).await {
+                    tracing::error!(error = ?err, "storing surveys failed");
+                }
+                if let Err(err) = record_cooldown(ctx, &res.data.cooldown).await {
+                    tracing::error!(error = ?err, "storing cooldown failed");
+                }
+                tracing::info!(waypoint = %waypoint_symbol, ship = %surveyor.symbol, surveys = res.data.surveys.len(), "re-surveyed before expiry");
+            }
+            Err(err) => tracing::warn!(waypoint = %waypoint_symbol, ship = %surveyor.symbol, error = %describe_api_error(&err), "re-survey failed"),
+        }
+    }
+    Ok(())
+}
+
+/// Keeps surveys available at each surveyed waypoint, surveying again when all of them are within `warn_mins` of expiring.
+pub fn start_survey_refresh_task(ctx: &Context, warn_mins: i32, health: DatabaseHealth) -> tokio::task::JoinHandle<()> {
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SURVEY_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            run_when_reachable(&ctx, &health, "checking survey expiry", || refresh_expiring_surveys(&ctx, warn_mins)).await;
+        }
+    })
+}
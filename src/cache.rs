@@ -0,0 +1,194 @@
+use std::{
+    any::Any,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use sqlx::PgPool;
+
+use crate::context::parse_env;
+use crate::spatial::{WaypointGraph, WaypointKdTree};
+use crate::st_util::{SystemRow, WaypointRow};
+
+/// How long cached responses are kept, unless overridden by `API_CACHE_TTL_SECS`.
+const DEFAULT_API_CACHE_TTL_SECS: u64 = 60;
+
+/// When an entry was cached, and the cached value.
+type CacheEntry = (Instant, Box<dyn Any + Send + Sync>);
+
+/// Short-lived cache of API responses that rarely change, keyed by a string such as `market:X1-DF55-A1`.
+/// Clones share the same entries.
+#[derive(Clone)]
+pub struct ApiCache {
+    entries: Arc<DashMap<String, CacheEntry>>,
+    ttl: Duration,
+}
+
+impl ApiCache {
+    pub fn new(ttl: Duration) -> Self {
+        ApiCache { entries: Arc::new(DashMap::new()), ttl }
+    }
+
+    /// Create a cache with the TTL from `API_CACHE_TTL_SECS`.
+    pub fn from_env() -> Self {
+        let ttl = parse_env("API_CACHE_TTL_SECS", DEFAULT_API_CACHE_TTL_SECS);
+        Self::new(Duration::from_secs(ttl))
+    }
+
+    /// Get a fresh cached value, dropping it if it has expired.
+    pub fn get<T: Clone + 'static>(&self, key: &str) -> Option<T> {
+        if let Some(entry) = self.entries.get(key) {
+            if entry.0.elapsed() < self.ttl {
+                return entry.1.downcast_ref::<T>().cloned();
+            }
+        }
+        self.entries.remove(key);
+        None
+    }
+
+    pub fn insert<T: Send + Sync + 'static>(&self, key: String, value: T) {
+        self.entries.insert(key, (Instant::now(), Box::new(value)));
+    }
+
+    /// Drop a cached value, after the data behind it changed.
+    pub fn invalidate(&self, key: &str) {
+        self.entries.remove(key);
+    }
+
+    /// Get a cached value, or fetch and cache it on a miss. Errors are not cached.
+    ///
+    /// # Errors
+    /// Propogates any error from `fetch`
+    pub async fn get_or_fetch<T, E, Fut>(&self, key: String, fetch: impl FnOnce() -> Fut) -> Result<T, E>
+    where
+        T: Clone + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+        let value = fetch().await?;
+        self.insert(key, value.clone());
+        Ok(value)
+    }
+}
+
+/// In-process copy of the waypoints and systems rows looked up by symbol, filled as they are read,
+/// and a [`WaypointGraph`] of the whole waypoints table built at startup or on first use.
+/// Unlike [`ApiCache`] entries do not expire, so anything writing to those tables must invalidate them.
+/// Clones share the same entries.
+#[derive(Clone, Default)]
+pub struct QueryCache {
+    waypoints: Arc<DashMap<String, WaypointRow>>,
+    systems: Arc<DashMap<String, SystemRow>>,
+    waypoint_graph: Arc<Mutex<WaypointGraphSlot>>,
+}
+
+/// The built [`WaypointGraph`], if still current, and how many times the waypoints table has been written to.
+/// The count stops a graph built from rows read before a write from being kept.
+#[derive(Default)]
+struct WaypointGraphSlot {
+    generation: u64,
+    graph: Option<WaypointGraph>,
+}
+
+impl QueryCache {
+    /// Get a waypoint row, from the cache if it has been read before.
+    ///
+    /// # Errors
+    /// Propogates any database error
+    pub async fn waypoint(&self, pool: &PgPool, symbol: &str) -> Result<Option<WaypointRow>, sqlx::Error> {
+        if let Some(row) = self.waypoints.get(symbol) {
+            return Ok(Some(row.clone()));
+        }
+        let row: Option<WaypointRow> = sqlx::query_as(
+            "SELECT * FROM waypoints WHERE symbol = $1")
+            .bind(symbol)
+            .fetch_optional(pool)
+            .await?;
+        if let Some(row) = &row {
+            self.waypoints.insert(symbol.to_string(), row.clone());
+        }
+        Ok(row)
+    }
+
+    /// Get a system row, from the cache if it has been read before.
+    ///
+    /// # Errors
+    /// Propogates any database error
+    pub async fn system(&self, pool: &PgPool, symbol: &str) -> Result<Option<SystemRow>, sqlx::Error> {
+        if let Some(row) = self.systems.get(symbol) {
+            return Ok(Some(row.clone()));
+        }
+        let row: Option<SystemRow> = sqlx::query_as(
+            "SELECT * FROM systems WHERE symbol = $1")
+            .bind(symbol)
+            .fetch_optional(pool)
+            .await?;
+        if let Some(row) = &row {
+            self.systems.insert(symbol.to_string(), row.clone());
+        }
+        Ok(row)
+    }
+
+    /// Get the spatial index of the waypoints in a system, building the graph if it isn't current.
+    /// Returns `None` if no waypoints are stored for the system.
+    ///
+    /// # Errors
+    /// Propogates any database error
+    pub async fn waypoint_tree(&self, pool: &PgPool, system_symbol: &str) -> Result<Option<Arc<WaypointKdTree>>, sqlx::Error> {
+        if let Some(graph) = &self.lock_waypoint_graph().graph {
+            return Ok(graph.system(system_symbol));
+        }
+        Ok(self.load_waypoint_graph(pool).await?.system(system_symbol))
+    }
+
+    /// Build the spatial index of every stored waypoint, keeping it unless the waypoints table was written to meanwhile.
+    ///
+    /// # Errors
+    /// Propogates any database error
+    pub async fn load_waypoint_graph(&self, pool: &PgPool) -> Result<WaypointGraph, sqlx::Error> {
+        let generation = self.lock_waypoint_graph().generation;
+        let waypoints: Vec<WaypointRow> = sqlx::query_as("SELECT * FROM waypoints")
+            .fetch_all(pool)
+            .await?;
+        let graph = WaypointGraph::new(&waypoints);
+        let mut slot = self.lock_waypoint_graph();
+        if slot.generation == generation {
+            slot.graph = Some(graph.clone());
+        }
+        Ok(graph)
+    }
+
+    fn lock_waypoint_graph(&self) -> MutexGuard<'_, WaypointGraphSlot> {
+        self.waypoint_graph.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Replace cached waypoint rows with `rows` just written to the waypoints table,
+    /// rebuilding the spatial index only for the systems they are in.
+    pub fn update_waypoints(&self, rows: &[WaypointRow]) {
+        for row in rows {
+            self.waypoints.insert(row.symbol.clone(), row.clone());
+        }
+        let mut slot = self.lock_waypoint_graph();
+        slot.generation += 1;
+        if let Some(graph) = &mut slot.graph {
+            graph.update(rows);
+        }
+    }
+
+    /// Drop cached waypoint rows and the spatial index, after the waypoints table was written to.
+    pub fn invalidate_waypoints(&self) {
+        self.waypoints.clear();
+        let mut slot = self.lock_waypoint_graph();
+        slot.generation += 1;
+        slot.graph = None;
+    }
+
+    /// Drop cached system rows, after the systems table was written to.
+    pub fn invalidate_systems(&self) {
+        self.systems.clear();
+    }
+}
@@ -0,0 +1,134 @@
+use std::{env, process, str::FromStr, sync::Arc, time::Duration};
+
+use inquire::{error::InquireResult, Select};
+use reqwest_middleware::{ClientWithMiddleware, Middleware};
+use spacedust::apis::configuration::Configuration;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Pool, Postgres};
+
+use crate::cache::{ApiCache, QueryCache};
+use crate::rate_limit::RateLimitMiddleware;
+use crate::retry::RetryMiddleware;
+
+/// Label for the unsuffixed `TOKEN` when it is offered alongside named profiles.
+const DEFAULT_PROFILE_LABEL: &str = "(default)";
+
+/// Reads `key` as a `T`, or `default` if it is unset. Exits if it is set to anything that doesn't parse as a `T`.
+pub fn parse_env<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Err(_) => default,
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            tracing::error!(%key, %value, expected = std::any::type_name::<T>(), "environment variable has an invalid value");
+            process::exit(1);
+        }),
+    }
+}
+
+/// Pool settings from `DB_MAX_CONNECTIONS`, `DB_MIN_CONNECTIONS` and `DB_ACQUIRE_TIMEOUT_SECS`.
+fn pool_options() -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(parse_env("DB_MAX_CONNECTIONS", 5))
+        .min_connections(parse_env("DB_MIN_CONNECTIONS", 1))
+        .acquire_timeout(Duration::from_secs(parse_env::<u32>("DB_ACQUIRE_TIMEOUT_SECS", 30).into()))
+}
+
+/// Connects to the database, retrying with exponential back-off so the client can start before the database is up.
+/// Waits `2^attempt` seconds between attempts, capped at `MAX_RETRY_DELAY_SECS`, and exits after `DB_CONNECT_RETRIES` retries.
+async fn connect_with_retry(database_url: &str) -> Pool<Postgres> {
+    let connect_timeout = Duration::from_secs(parse_env::<u32>("DB_CONNECT_TIMEOUT_SECS", 30).into());
+    let max_retries = parse_env("DB_CONNECT_RETRIES", 5);
+    let max_delay = Duration::from_secs(parse_env::<u32>("MAX_RETRY_DELAY_SECS", 30).into());
+
+    let mut attempt = 0;
+    loop {
+        let error = match tokio::time::timeout(connect_timeout, pool_options().connect(database_url)).await {
+            Ok(Ok(db_pool)) => return db_pool,
+            Ok(Err(err)) => err.to_string(),
+            Err(_) => format!("timed out after {}s", connect_timeout.as_secs()),
+        };
+        if attempt >= max_retries {
+            tracing::error!(%error, retries = attempt, "database connection failed");
+            process::exit(1);
+        }
+        let delay = Duration::from_secs(2_u64.saturating_pow(attempt)).min(max_delay);
+        attempt += 1;
+        tracing::warn!(%error, attempt, delay_secs = delay.as_secs(), "database connection failed, retrying");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Everything needed to make API and database calls as one agent.
+/// Cloning is cheap, the HTTP client and the pool are both reference counted.
+#[derive(Clone)]
+pub struct Context {
+    /// [`Configuration`] object for use in all API calls.
+    /// Sets API key and manages rate limit.
+    pub configuration: Configuration,
+    pub db_pool: Pool<Postgres>,
+    pub api_cache: ApiCache,
+    pub query_cache: QueryCache,
+}
+
+impl Context {
+    /// Pick an agent profile and connect to its database.
+    ///
+    /// Every `TOKEN_<name>` environment variable is a profile, using `DATABASE_URL_<name>` if set and
+    /// `DATABASE_URL` otherwise. When there is more than one choice the user is asked which to use.
+    /// With only `TOKEN` set this behaves as a single unnamed profile.
+    /// Exits the process if the token or database URL is missing, or the database connection fails.
+    ///
+    /// # Errors
+    /// Propogates any error from the profile prompt, including the user cancelling it.
+    pub async fn from_profile() -> InquireResult<Self> {
+        let profile = select_profile()?;
+        let (token_var, database_url) = match &profile {
+            Some(name) => (
+                format!("TOKEN_{name}"),
+                env::var(format!("DATABASE_URL_{name}")).or_else(|_| env::var("DATABASE_URL")),
+            ),
+            None => ("TOKEN".to_string(), env::var("DATABASE_URL")),
+        };
+
+        let Ok(token) = env::var(&token_var) else {
+            tracing::error!(variable = %token_var, "environment variable expected");
+            process::exit(1);
+        };
+        let Ok(database_url) = database_url else {
+            tracing::error!(variable = "DATABASE_URL", "environment variable expected");
+            process::exit(1);
+        };
+
+        let mut configuration = Configuration::new();
+        configuration.bearer_access_token = Some(token);
+        // Retries go back through the rate limiter, so it comes second and sits closer to the client.
+        let middleware: Box<[Arc<dyn Middleware>]> = Box::new([Arc::new(RetryMiddleware::from_env()), Arc::new(RateLimitMiddleware)]);
+        configuration.client = ClientWithMiddleware::new(reqwest::Client::new(), middleware);
+
+        let db_pool = connect_with_retry(&database_url).await;
+
+        Ok(Context { configuration, db_pool, api_cache: ApiCache::from_env(), query_cache: QueryCache::default() })
+    }
+}
+
+/// Get the name of the chosen `TOKEN_<name>` profile, or `None` for the plain `TOKEN`.
+fn select_profile() -> InquireResult<Option<String>> {
+    let mut names: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("TOKEN_").map(String::from))
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+
+    let has_default = env::var("TOKEN").is_ok();
+    match (names.len(), has_default) {
+        (0, _) => return Ok(None),
+        (1, false) => return Ok(names.pop()),
+        _ => {}
+    }
+
+    let mut options = names;
+    if has_default {
+        options.insert(0, DEFAULT_PROFILE_LABEL.to_string());
+    }
+    let choice = Select::new("Select agent profile", options).prompt()?;
+    Ok((choice != DEFAULT_PROFILE_LABEL).then_some(choice))
+}
@@ -0,0 +1,398 @@
+//! Export and import of the cached systems and waypoints tables as RFC 4180 CSV files.
+
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::context::Context;
+use crate::BIND_LIMIT;
+
+pub const SYSTEMS_FILE: &str = "systems.csv";
+pub const WAYPOINTS_FILE: &str = "waypoints.csv";
+
+const SYSTEMS_HEADER: [&str; 7] = ["symbol", "sector_symbol", "type", "x", "y", "factions", "controlling_faction"];
+const WAYPOINTS_HEADER: [&str; 8] = ["symbol", "type", "system_symbol", "x", "y", "is_marketplace", "is_shipyard", "traits"];
+
+/// Separator for list values (system factions, waypoint traits) within a single field.
+const LIST_SEPARATOR: char = '|';
+
+/// Why an export or import failed.
+#[derive(Debug)]
+pub enum CsvError {
+    Io { file: String, source: std::io::Error },
+    Malformed { file: String, line: usize, reason: String },
+    Database(sqlx::Error),
+}
+
+impl Display for CsvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvError::Io { file, source } => write!(f, "{file}: {source}"),
+            CsvError::Malformed { file, line, reason } => write!(f, "{file} line {line}: {reason}"),
+            CsvError::Database(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for CsvError {
+    fn from(err: sqlx::Error) -> Self {
+        CsvError::Database(err)
+    }
+}
+
+#[derive(FromRow)]
+struct SystemRecord {
+    symbol: Option<String>,
+    sector_symbol: Option<String>,
+    r#type: Option<String>,
+    x: Option<i32>,
+    y: Option<i32>,
+    factions: Option<Vec<String>>,
+    controlling_faction: Option<String>,
+}
+
+#[derive(FromRow)]
+struct WaypointRecord {
+    symbol: Option<String>,
+    r#type: Option<String>,
+    system_symbol: Option<String>,
+    x: Option<i32>,
+    y: Option<i32>,
+    is_marketplace: Option<bool>,
+    is_shipyard: Option<bool>,
+    traits: Vec<String>,
+}
+
+/// Quotes `field` if it contains a comma, quote or line break, doubling any quotes inside it.
+fn escape_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Formats one CSV record, including its terminating CRLF.
+fn format_record<S: AsRef<str>>(fields: &[S]) -> String {
+    let mut record = fields.iter()
+        .map(|field| escape_field(field.as_ref()))
+        .collect::<Vec<_>>()
+        .join(",");
+    record.push_str("\r\n");
+    record
+}
+
+fn optional_field<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map_or_else(String::new, ToString::to_string)
+}
+
+/// Splits `contents` into records of fields, handling quoted fields that contain separators, quotes or line breaks.
+/// Returns the line each record starts on alongside its fields.
+fn parse_records(file: &str, contents: &str) -> Result<Vec<(usize, Vec<String>)>, CsvError> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            '"' => return Err(CsvError::Malformed { file: file.to_string(), line, reason: "unexpected quote in unquoted field".to_string() }),
+            ',' => fields.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut fields)));
+                line += 1;
+                record_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(CsvError::Malformed { file: file.to_string(), line, reason: "unterminated quoted field".to_string() });
+    }
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push((record_line, fields));
+    }
+    Ok(records)
+}
+
+async fn write_file(path: &Path, contents: &str) -> Result<(), CsvError> {
+    let io_error = |source| CsvError::Io { file: path.display().to_string(), source };
+    let mut file = File::create(path).await.map_err(io_error)?;
+    file.write_all(contents.as_bytes()).await.map_err(io_error)?;
+    file.flush().await.map_err(io_error)
+}
+
+async fn read_records(directory: &Path, file_name: &str, header: &[&str]) -> Result<Vec<(usize, Vec<String>)>, CsvError> {
+    let path = directory.join(file_name);
+    let file = path.display().to_string();
+    let contents = tokio::fs::read_to_string(&path).await.map_err(|source| CsvError::Io { file: file.clone(), source })?;
+
+    let mut records = parse_records(&file, &contents)?.into_iter();
+    match records.next() {
+        Some((_, found)) if found == header => {}
+        _ => return Err(CsvError::Malformed { file, line: 1, reason: format!("header must be {}", header.join(",")) }),
+    }
+    let records: Vec<_> = records.collect();
+    if let Some((line, fields)) = records.iter().find(|(_, fields)| fields.len() != header.len()) {
+        return Err(CsvError::Malformed { file, line: *line, reason: format!("expected {} fields, found {}", header.len(), fields.len()) });
+    }
+    Ok(records)
+}
+
+fn non_empty(field: &str) -> Option<&str> {
+    Some(field).filter(|field| !field.is_empty())
+}
+
+fn split_list(field: &str) -> Vec<&str> {
+    field.split(LIST_SEPARATOR).filter(|item| !item.is_empty()).collect()
+}
+
+/// `field`, or an error naming `column` if it is empty. Used for columns the rest of the client reads as non-null.
+fn required_field<'a>(file: &str, line: usize, column: &str, field: &'a str) -> Result<&'a str, CsvError> {
+    non_empty(field).ok_or_else(|| CsvError::Malformed { file: file.to_string(), line, reason: format!("{column} must not be empty") })
+}
+
+fn parse_required<T: std::str::FromStr>(file: &str, line: usize, column: &str, field: &str) -> Result<T, CsvError> {
+    parse_field(file, line, column, required_field(file, line, column, field)?)?
+        .ok_or_else(|| CsvError::Malformed { file: file.to_string(), line, reason: format!("{column} must not be empty") })
+}
+
+fn parse_field<T: std::str::FromStr>(file: &str, line: usize, column: &str, field: &str) -> Result<Option<T>, CsvError> {
+    non_empty(field)
+        .map(str::parse)
+        .transpose()
+        .map_err(|_| CsvError::Malformed { file: file.to_string(), line, reason: format!("invalid {column} value {field:?}") })
+}
+
+/// Writes `systems.csv` and `waypoints.csv` into `directory`. Returns the number of systems and waypoints written.
+///
+/// # Errors
+/// Returns an error if the tables can't be read or either file can't be written
+pub async fn export_csv(ctx: &Context, directory: &Path) -> Result<(usize, usize), CsvError> {
+    let systems: Vec<SystemRecord> = sqlx::query_as(
+        "SELECT symbol, sector_symbol, type, x, y, factions, controlling_faction FROM systems ORDER BY symbol")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    let waypoints: Vec<WaypointRecord> = sqlx::query_as(
+        "SELECT w.symbol, w.type, w.system_symbol, w.x, w.y, w.is_marketplace, w.is_shipyard,
+            ARRAY(SELECT t.trait_symbol FROM waypoint_traits t WHERE t.waypoint_symbol = w.symbol ORDER BY t.trait_symbol) AS traits
+        FROM waypoints w ORDER BY w.symbol")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    let mut contents = format_record(&SYSTEMS_HEADER);
+    for system in &systems {
+        contents.push_str(&format_record(&[
+            optional_field(&system.symbol),
+            optional_field(&system.sector_symbol),
+            optional_field(&system.r#type),
+            optional_field(&system.x),
+            optional_field(&system.y),
+            system.factions.as_deref().unwrap_or_default().join(&LIST_SEPARATOR.to_string()),
+            optional_field(&system.controlling_faction),
+        ]));
+    }
+    write_file(&directory.join(SYSTEMS_FILE), &contents).await?;
+
+    let mut contents = format_record(&WAYPOINTS_HEADER);
+    for waypoint in &waypoints {
+        contents.push_str(&format_record(&[
+            optional_field(&waypoint.symbol),
+            optional_field(&waypoint.r#type),
+            optional_field(&waypoint.system_symbol),
+            optional_field(&waypoint.x),
+            optional_field(&waypoint.y),
+            optional_field(&waypoint.is_marketplace),
+            optional_field(&waypoint.is_shipyard),
+            waypoint.traits.join(&LIST_SEPARATOR.to_string()),
+        ]));
+    }
+    write_file(&directory.join(WAYPOINTS_FILE), &contents).await?;
+
+    Ok((systems.len(), waypoints.len()))
+}
+
+/// Replaces the systems, waypoints and waypoint traits tables with the contents of `systems.csv` and `waypoints.csv` in `directory`.
+/// Imported traits only carry their symbol, since names and descriptions aren't exported.
+/// Returns the number of systems and waypoints imported.
+///
+/// # Errors
+/// Returns an error if either file is missing or malformed, or the tables can't be written. Nothing is changed on error.
+pub async fn import_csv(ctx: &Context, directory: &Path) -> Result<(usize, usize), CsvError> {
+    let systems = read_records(directory, SYSTEMS_FILE, &SYSTEMS_HEADER).await?;
+    let waypoints = read_records(directory, WAYPOINTS_FILE, &WAYPOINTS_HEADER).await?;
+
+    // Validate every field before touching the database. Symbols, types and coordinates must be present,
+    // since the systems and waypoints rows are read back into non-optional fields.
+    let mut system_coordinates = Vec::with_capacity(systems.len());
+    for (line, fields) in &systems {
+        for (column, field) in SYSTEMS_HEADER.iter().zip(fields).take(3) {
+            required_field(SYSTEMS_FILE, *line, column, field)?;
+        }
+        system_coordinates.push((
+            parse_required::<i32>(SYSTEMS_FILE, *line, "x", &fields[3])?,
+            parse_required::<i32>(SYSTEMS_FILE, *line, "y", &fields[4])?,
+        ));
+    }
+    let mut waypoint_values = Vec::with_capacity(waypoints.len());
+    for (line, fields) in &waypoints {
+        for (column, field) in WAYPOINTS_HEADER.iter().zip(fields).take(3) {
+            required_field(WAYPOINTS_FILE, *line, column, field)?;
+        }
+        waypoint_values.push((
+            parse_required::<i32>(WAYPOINTS_FILE, *line, "x", &fields[3])?,
+            parse_required::<i32>(WAYPOINTS_FILE, *line, "y", &fields[4])?,
+            parse_field::<bool>(WAYPOINTS_FILE, *line, "is_marketplace", &fields[5])?,
+            parse_field::<bool>(WAYPOINTS_FILE, *line, "is_shipyard", &fields[6])?,
+        ));
+    }
+
+    let mut transaction = ctx.db_pool.begin().await?;
+    sqlx::query("DELETE FROM waypoint_traits").execute(&mut transaction).await?;
+    sqlx::query("DELETE FROM waypoints").execute(&mut transaction).await?;
+    sqlx::query("DELETE FROM systems").execute(&mut transaction).await?;
+
+    let systems_rows: Vec<_> = systems.iter().zip(&system_coordinates).collect();
+    for chunk in systems_rows.chunks(BIND_LIMIT / 7) {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO systems(symbol, sector_symbol, type, x, y, factions, controlling_faction) "
+            );
+        query_builder.push_values(chunk, |mut b, ((_, fields), (x, y))| {
+            b.push_bind(&fields[0])
+                .push_bind(&fields[1])
+                .push_bind(&fields[2])
+                .push_bind(*x)
+                .push_bind(*y)
+                .push_bind(split_list(&fields[5]))
+                .push_bind(non_empty(&fields[6]));
+        });
+        query_builder.build().execute(&mut transaction).await?;
+    }
+
+    let waypoint_rows: Vec<_> = waypoints.iter().zip(&waypoint_values).collect();
+    for chunk in waypoint_rows.chunks(BIND_LIMIT / 7) {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO waypoints(symbol, type, system_symbol, x, y, is_marketplace, is_shipyard) "
+            );
+        query_builder.push_values(chunk, |mut b, ((_, fields), (x, y, is_marketplace, is_shipyard))| {
+            b.push_bind(&fields[0])
+                .push_bind(&fields[1])
+                .push_bind(&fields[2])
+                .push_bind(*x)
+                .push_bind(*y)
+                .push_bind(*is_marketplace)
+                .push_bind(*is_shipyard);
+        });
+        query_builder.build().execute(&mut transaction).await?;
+    }
+
+    let traits: Vec<(&str, &str)> = waypoints.iter()
+        .flat_map(|(_, fields)| split_list(&fields[7]).into_iter().map(|trait_symbol| (fields[0].as_str(), trait_symbol)))
+        .collect();
+    for chunk in traits.chunks(BIND_LIMIT / 2) {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO waypoint_traits(waypoint_symbol, trait_symbol) "
+            );
+        query_builder.push_values(chunk, |mut b, (waypoint_symbol, trait_symbol)| {
+            b.push_bind(*waypoint_symbol).push_bind(*trait_symbol);
+        });
+        query_builder.push(" ON CONFLICT DO NOTHING");
+        query_builder.build().execute(&mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+    ctx.query_cache.invalidate_waypoints();
+    ctx.query_cache.invalidate_systems();
+    Ok((systems.len(), waypoints.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(records: &[(usize, Vec<String>)]) -> Vec<Vec<&str>> {
+        records.iter().map(|(_, fields)| fields.iter().map(String::as_str).collect()).collect()
+    }
+
+    #[test]
+    fn escape_field_quotes_only_when_needed() {
+        assert_eq!(escape_field("X1-DF55"), "X1-DF55");
+        assert_eq!(escape_field("a,b"), "\"a,b\"");
+        assert_eq!(escape_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape_field("two\r\nlines"), "\"two\r\nlines\"");
+    }
+
+    #[test]
+    fn parse_records_reads_quoted_fields() {
+        let records = parse_records("test.csv", "a,\"b,c\",\"d \"\"e\"\"\"\n").unwrap();
+        assert_eq!(fields(&records), vec![vec!["a", "b,c", "d \"e\""]]);
+    }
+
+    #[test]
+    fn parse_records_handles_crlf_and_embedded_line_breaks() {
+        let records = parse_records("test.csv", "a,b\r\n\"c\r\nd\",e\r\nf,\r\n").unwrap();
+        assert_eq!(fields(&records), vec![vec!["a", "b"], vec!["c\r\nd", "e"], vec!["f", ""]]);
+        assert_eq!(records.iter().map(|(line, _)| *line).collect::<Vec<_>>(), vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn parse_records_reads_last_record_without_line_break() {
+        let records = parse_records("test.csv", "a,b\nc,d").unwrap();
+        assert_eq!(fields(&records), vec![vec!["a", "b"], vec!["c", "d"]]);
+    }
+
+    #[test]
+    fn format_record_round_trips() {
+        let original = ["plain", "with,comma", "with \"quote\"", "with\r\nbreak", ""];
+        let records = parse_records("test.csv", &format_record(&original)).unwrap();
+        assert_eq!(fields(&records), vec![original.to_vec()]);
+    }
+
+    #[test]
+    fn parse_records_rejects_unterminated_quotes() {
+        let err = parse_records("test.csv", "a,\"b\nc\n").unwrap_err();
+        assert!(matches!(err, CsvError::Malformed { line: 3, .. }), "{err}");
+    }
+
+    #[test]
+    fn parse_records_rejects_quote_inside_unquoted_field() {
+        let err = parse_records("test.csv", "ab\"c\n").unwrap_err();
+        assert!(matches!(err, CsvError::Malformed { line: 1, .. }), "{err}");
+    }
+
+    #[test]
+    fn required_fields_reject_empty_values() {
+        assert_eq!(required_field("test.csv", 1, "symbol", "X1-DF55").unwrap(), "X1-DF55");
+        assert!(required_field("test.csv", 1, "symbol", "").is_err());
+        assert_eq!(parse_required::<i32>("test.csv", 1, "x", "-12").unwrap(), -12);
+        assert!(parse_required::<i32>("test.csv", 1, "x", "").is_err());
+        assert!(parse_required::<i32>("test.csv", 1, "x", "east").is_err());
+    }
+}
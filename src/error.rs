@@ -0,0 +1,43 @@
+//! The error menu actions stop with, reported to the user by the main loop.
+
+use std::fmt::{self, Display, Formatter};
+
+use inquire::InquireError;
+
+#[derive(Debug)]
+pub enum AppError {
+    Database(sqlx::Error),
+    Prompt(InquireError),
+    /// An API call failed, described with [`crate::st_util::describe_api_error`].
+    Api(String),
+    Io(std::io::Error),
+}
+
+impl Display for AppError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Database(err) => write!(f, "database error: {err}"),
+            AppError::Prompt(err) => write!(f, "prompt failed: {err}"),
+            AppError::Api(description) => write!(f, "API error: {description}"),
+            AppError::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        AppError::Database(err)
+    }
+}
+
+impl From<InquireError> for AppError {
+    fn from(err: InquireError) -> Self {
+        AppError::Prompt(err)
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        AppError::Io(err)
+    }
+}
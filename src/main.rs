@@ -1,172 +1,468 @@
-#![allow(clippy::expect_used)]
-
+mod background;
+mod cache;
+mod context;
+mod csv_io;
+mod error;
 mod rate_limit;
+mod retry;
+mod setup;
+mod spatial;
 mod st_util;
 
-use crate::rate_limit::RateLimitMiddleware;
+use crate::context::Context;
+use crate::error::AppError;
+use crate::st_util::{describe_api_error, SystemRow, WaypointRow};
 
 use std::fmt::Debug;
 use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
     env,
+    io::{self, Write},
+    path::PathBuf,
     process,
-    sync::Arc
+    time::Duration
 };
 
-use inquire::{Select, Text};
-use strum::{EnumIter, IntoEnumIterator, Display};
+use inquire::error::{CustomUserError, InquireError, InquireResult};
+use inquire::validator::Validation;
+use inquire::{Confirm, CustomType, MultiSelect, Select, Text};
+use strum::{EnumIter, IntoEnumIterator};
+use tracing_subscriber::EnvFilter;
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
 use once_cell::sync::Lazy;
-use spacedust::apis::configuration::Configuration;
-use spacedust::models::System;
-use sqlx::postgres::PgPoolOptions;
-use sqlx::{Pool, Postgres, QueryBuilder};
-use reqwest_middleware::{Middleware, ClientWithMiddleware};
-use tokio::sync::OnceCell;
+use regex::Regex;
+use spacedust::models::{
+    Contract, Cooldown, ExtractResourcesRequest, JumpShipRequest, Market, MarketTradeGood, NavigateShipRequest, PatchShipNavRequest,
+    PurchaseCargoRequest, PurchaseShipRequest, SellCargoRequest, Ship, ShipCargo, ShipNavFlightMode, ShipRole, ShipType, ShipNavStatus, System,
+    TransferCargoRequest, Waypoint, WaypointType,
+};
+use sqlx::{Postgres, QueryBuilder};
 
 //----------------------------------------------------------------------
 //                              SETUP
 //----------------------------------------------------------------------
 
+/// Loads `.env`, then starts logging at the level from `RUST_LOG` (default `info`).
 fn setup_dotenv() {
-    if dotenvy::dotenv().is_err() {
-        eprintln!(".env file expected");
+    // Loaded first so that `RUST_LOG` can be set in `.env`.
+    let loaded = dotenvy::dotenv().is_ok();
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+    if !loaded {
+        tracing::error!(".env file expected");
         process::exit(1);
     }
 }
 
-/// [`Configuration`] object for use in all API calls.
-/// Sets API key and manages rate limit.
-static CONFIGURATION: Lazy<Configuration> = Lazy::new(|| {
-    let Ok(token) = env::var("TOKEN") else {
-        eprintln!("TOKEN environment variable expected");
-        process::exit(1);
-    };
-
-    let mut configuration = Configuration::new();
-    configuration.bearer_access_token = Some(token);
-    let middleware: Box<[Arc<dyn Middleware>]> = Box::new([Arc::new(RateLimitMiddleware)]);
-    configuration.client = ClientWithMiddleware::new(reqwest::Client::new(), middleware);
-    configuration
-});
-
-static DB_POOL: OnceCell<Pool<Postgres>> = OnceCell::const_new();
-async fn get_global_db_pool() -> &'static Pool<Postgres> {
-    DB_POOL.get_or_init(|| async {
-        let Ok(database_url) = env::var("DATABASE_URL") else {
-            eprintln!("DATABASE_URL environment variable expected");
-            process::exit(1);
-        };
-        let Ok(pool) = PgPoolOptions::new()
-            .max_connections(5)
-            .connect(&database_url)
-            .await 
-        else {
-            eprintln!("Database connection failed");
-            process::exit(1);
-        };
-        pool
-    }).await
-}
-
 const BIND_LIMIT: usize = 65535;
 
-async fn create_systems_table (systems : &[System]) {
-    println!("Creating systems table");
+/// Inserts `systems`, updating any that are already stored.
+#[tracing::instrument(skip_all, fields(rows = systems.len()))]
+async fn upsert_systems (ctx: &Context, systems : &[System]) -> Result<(), sqlx::Error> {
+    tracing::info!("updating systems table");
 
-    sqlx::query("DROP TABLE IF EXISTS systems").execute(get_global_db_pool().await).await.expect("Delete systems table if it exists");
+    let mut transaction = ctx.db_pool.begin().await?;
 
-    sqlx::query("CREATE TABLE systems (
-                symbol              text,
-                sector_symbol       text,
-                type                text,
-                x                   int,
-                y                   int,
-                factions            text[]
-            )")
-        .execute(get_global_db_pool().await)
-        .await
-        .expect("Create systems table");
-    
-    let mut transaction = get_global_db_pool().await.begin().await.expect("Start insertion transaction");
-
-    for systems_chunk in systems.chunks(BIND_LIMIT / 6) {
+    let rows: Vec<SystemRow> = systems.iter().map(SystemRow::from).collect();
+    for rows_chunk in rows.chunks(BIND_LIMIT / 7) {
         let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
-            "INSERT INTO systems(symbol, sector_symbol, type, x, y, factions) "
+            "INSERT INTO systems(symbol, sector_symbol, type, x, y, factions, controlling_faction) "
             );
-        query_builder.push_values(systems_chunk, |mut b, system| {
-            b.push_bind(&system.symbol)
-                .push_bind(&system.sector_symbol)
-                .push_bind(system.r#type.to_string())
-                .push_bind(system.x)
-                .push_bind(system.y)
-                .push_bind(system.factions.iter().map(|x| &*x.symbol).collect::<Vec<&str>>());
+        query_builder.push_values(rows_chunk, |mut b, row| {
+            b.push_bind(&row.symbol)
+                .push_bind(&row.sector_symbol)
+                .push_bind(&row.system_type)
+                .push_bind(row.x)
+                .push_bind(row.y)
+                .push_bind(&row.factions)
+                .push_bind(&row.controlling_faction);
         });
-        query_builder.build().execute(&mut transaction).await.expect("Insert into systems table");
+        query_builder.push(" ON CONFLICT (symbol) DO UPDATE SET sector_symbol = EXCLUDED.sector_symbol, type = EXCLUDED.type,
+            x = EXCLUDED.x, y = EXCLUDED.y, factions = EXCLUDED.factions, controlling_faction = EXCLUDED.controlling_faction");
+        query_builder.build().execute(&mut transaction).await?;
     }
 
-    transaction.commit().await.expect("Commit insertion transaction");
+    transaction.commit().await?;
+    ctx.query_cache.invalidate_systems();
+    Ok(())
 }
 
-async fn create_waypoints_table (systems : &[System]) {
-    println!("Creating waypoints table");
-
-    sqlx::query("DROP TABLE IF EXISTS waypoints").execute(get_global_db_pool().await).await.expect("Delete waypoints table if it exists");
+/// Inserts the waypoints of `systems`, updating any that are already stored.
+/// Known traits and marketplace and shipyard flags are kept, since the systems listing doesn't include them.
+#[tracing::instrument(skip_all, fields(rows = systems.iter().map(|system| system.waypoints.len()).sum::<usize>()))]
+async fn upsert_waypoints (ctx: &Context, systems : &[System]) -> Result<(), sqlx::Error> {
+    tracing::info!("updating waypoints table");
 
-    sqlx::query("CREATE TABLE waypoints (
-                symbol              text,
-                type                text,
-                system_symbol       text,
-                x                   int,
-                y                   int,
-                is_marketplace      boolean,
-                is_shipyard         boolean
-            )")
-        .execute(get_global_db_pool().await)
-        .await
-        .expect("Create waypoints table");
-    
-    let mut transaction = get_global_db_pool().await.begin().await.expect("Start insertion transaction");
+    let mut transaction = ctx.db_pool.begin().await?;
 
-    for system in systems {
-        if system.waypoints.is_empty() {
-            continue;
-        }
+    let waypoints: Vec<_> = systems.iter()
+        .flat_map(|system| system.waypoints.iter().map(move |waypoint| (system, waypoint)))
+        .collect();
+    for waypoints_chunk in waypoints.chunks(BIND_LIMIT / 5) {
         let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
             "INSERT INTO waypoints(symbol, type, system_symbol, x, y) "
             );
-        query_builder.push_values(system.waypoints.iter(), |mut b, waypoint| {
+        query_builder.push_values(waypoints_chunk, |mut b, (system, waypoint)| {
             b.push_bind(&waypoint.symbol)
                 .push_bind(waypoint.r#type.to_string())
                 .push_bind(&system.symbol)
                 .push_bind(waypoint.x)
                 .push_bind(waypoint.y);
         });
-        query_builder.build().execute(&mut transaction).await.expect("Insert into waypoints table");
+        query_builder.push(" ON CONFLICT (symbol) DO UPDATE SET type = EXCLUDED.type, system_symbol = EXCLUDED.system_symbol,
+            x = EXCLUDED.x, y = EXCLUDED.y");
+        query_builder.build().execute(&mut transaction).await?;
     }
 
-    transaction.commit().await.expect("Commit insertion transaction");
+    transaction.commit().await?;
+    ctx.query_cache.invalidate_waypoints();
+    Ok(())
 }
 
-async fn ensure_systems_data () {
+/// Replaces the stored traits of each of `waypoints` with their current ones, and upserts their waypoints rows
+/// with up to date marketplace and shipyard flags.
+async fn store_waypoint_traits (ctx: &Context, waypoints : &[Waypoint]) -> Result<(), sqlx::Error> {
+    let mut transaction = ctx.db_pool.begin().await?;
 
-    let systems_exists = sqlx::query("SELECT FROM pg_tables WHERE schemaname = 'public' AND tablename = 'systems'")
-        .execute(get_global_db_pool().await)
-        .await
-        .expect("Postgres test query")
-        .rows_affected() > 0;
+    let symbols: Vec<&str> = waypoints.iter().map(|waypoint| waypoint.symbol.as_str()).collect();
+    sqlx::query("DELETE FROM waypoint_traits WHERE waypoint_symbol = ANY($1)")
+        .bind(&symbols)
+        .execute(&mut transaction)
+        .await?;
 
-    let waypoints_exists = sqlx::query("SELECT FROM pg_tables WHERE schemaname = 'public' AND tablename = 'waypoints'")
-        .execute(get_global_db_pool().await)
-        .await
-        .expect("Postgres test query")
-        .rows_affected() > 0;
+    let traits: Vec<_> = waypoints.iter()
+        .flat_map(|waypoint| waypoint.traits.iter().map(move |waypoint_trait| (&waypoint.symbol, waypoint_trait)))
+        .collect();
+    for chunk in traits.chunks(BIND_LIMIT / 4) {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO waypoint_traits(waypoint_symbol, trait_symbol, name, description) "
+            );
+        query_builder.push_values(chunk, |mut b, (waypoint_symbol, waypoint_trait)| {
+            b.push_bind(*waypoint_symbol)
+                .push_bind(st_util::trait_symbol_name(waypoint_trait))
+                .push_bind(&waypoint_trait.name)
+                .push_bind(&waypoint_trait.description);
+        });
+        query_builder.build().execute(&mut transaction).await?;
+    }
+
+    let rows: Vec<WaypointRow> = waypoints.iter().map(WaypointRow::from).collect();
+    for rows_chunk in rows.chunks(BIND_LIMIT / 7) {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO waypoints(symbol, type, system_symbol, x, y, is_marketplace, is_shipyard) "
+            );
+        query_builder.push_values(rows_chunk, |mut b, row| {
+            b.push_bind(&row.symbol)
+                .push_bind(&row.waypoint_type)
+                .push_bind(&row.system_symbol)
+                .push_bind(row.x)
+                .push_bind(row.y)
+                .push_bind(row.is_marketplace)
+                .push_bind(row.is_shipyard);
+        });
+        query_builder.push(" ON CONFLICT (symbol) DO UPDATE SET type = EXCLUDED.type, system_symbol = EXCLUDED.system_symbol,
+            x = EXCLUDED.x, y = EXCLUDED.y, is_marketplace = EXCLUDED.is_marketplace, is_shipyard = EXCLUDED.is_shipyard");
+        query_builder.build().execute(&mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+    ctx.query_cache.update_waypoints(&rows);
+    Ok(())
+}
+
+/// Records the current prices of every trade good at a market, replacing any earlier observation.
+/// Markets only list prices while one of your ships is there, so this does nothing otherwise.
+async fn record_market_prices (ctx: &Context, market : &Market) -> Result<(), sqlx::Error> {
+    let Some(goods) = market.trade_goods.as_ref().filter(|goods| !goods.is_empty()) else {
+        return Ok(());
+    };
+
+    for goods_chunk in goods.chunks(BIND_LIMIT / 6) {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO market_prices(waypoint_symbol, trade_symbol, purchase_price, sell_price, trade_volume, supply) "
+            );
+        query_builder.push_values(goods_chunk, |mut b, good| {
+            b.push_bind(&market.symbol)
+                .push_bind(&good.symbol)
+                .push_bind(good.purchase_price)
+                .push_bind(good.sell_price)
+                .push_bind(good.trade_volume)
+                .push_bind(st_util::api_name(&good.supply));
+        });
+        query_builder.push(" ON CONFLICT (waypoint_symbol, trade_symbol) DO UPDATE SET
+                    purchase_price = EXCLUDED.purchase_price,
+                    sell_price = EXCLUDED.sell_price,
+                    trade_volume = EXCLUDED.trade_volume,
+                    supply = EXCLUDED.supply,
+                    observed_at = NOW()");
+        query_builder.build().execute(&ctx.db_pool).await?;
+    }
+
+    check_watchlist_alerts(ctx, market).await
+}
+
+#[derive(sqlx::FromRow)]
+struct WatchlistAlert {
+    trade_symbol: String,
+    purchase_price: i32,
+    sell_price: i32,
+    min_sell_alert: Option<i64>,
+    max_buy_alert: Option<i64>,
+}
+
+/// Prints an alert for every watched good whose price at `market` crossed its threshold.
+async fn check_watchlist_alerts (ctx: &Context, market : &Market) -> Result<(), sqlx::Error> {
+    let alerts: Vec<WatchlistAlert> = sqlx::query_as(
+        "SELECT m.trade_symbol, m.purchase_price, m.sell_price, w.min_sell_alert, w.max_buy_alert
+        FROM market_prices m JOIN trade_watchlist w ON w.trade_symbol = m.trade_symbol
+        WHERE m.waypoint_symbol = $1
+            AND (m.sell_price >= w.min_sell_alert OR m.purchase_price <= w.max_buy_alert)")
+        .bind(&market.symbol)
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    for alert in alerts {
+        if let Some(min_sell) = alert.min_sell_alert.filter(|min_sell| i64::from(alert.sell_price) >= *min_sell) {
+            println!("Watchlist alert: {} buys {} for {} (alert at {min_sell}+)", market.symbol, alert.trade_symbol, alert.sell_price);
+        }
+        if let Some(max_buy) = alert.max_buy_alert.filter(|max_buy| i64::from(alert.purchase_price) <= *max_buy) {
+            println!("Watchlist alert: {} sells {} for {} (alert at {max_buy} or less)", market.symbol, alert.trade_symbol, alert.purchase_price);
+        }
+    }
+    Ok(())
+}
+
+/// Fetches the waypoints of the agent's headquarters system so the traits table isn't empty after a rebuild.
+/// API errors are printed rather than returned, since the traits are filled in again as waypoints are fetched.
+async fn seed_waypoint_traits (ctx: &Context) -> Result<(), sqlx::Error> {
+    let agent = match spacedust::apis::agents_api::get_my_agent(&ctx.configuration).await {
+        Ok(res) => res.data,
+        Err(err_res) => {
+            println!("Could not seed waypoint traits: {}", describe_api_error(&err_res));
+            return Ok(());
+        }
+    };
+    let system_symbol = match st_util::system_symbol_from_waypoint_symbol(&agent.headquarters) {
+        Ok(system_symbol) => system_symbol,
+        Err(err) => {
+            println!("Could not seed waypoint traits: {err}");
+            return Ok(());
+        }
+    };
+    match st_util::list_system_waypoints(ctx, &system_symbol).await {
+        Ok(waypoints) => store_waypoint_traits(ctx, &waypoints).await,
+        Err(err) => {
+            println!("Could not seed waypoint traits: {}", describe_api_error(&err));
+            Ok(())
+        }
+    }
+}
+
+/// Upserts `contracts` and marks any stored contract missing from them as expired.
+/// Returns the number of contracts newly marked expired.
+async fn upsert_contracts (ctx: &Context, contracts : &[Contract]) -> Result<u64, sqlx::Error> {
+    let mut transaction = ctx.db_pool.begin().await?;
+
+    for contracts_chunk in contracts.chunks(BIND_LIMIT / 4) {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO contracts(id, accepted, fulfilled, deadline) "
+            );
+        query_builder.push_values(contracts_chunk, |mut b, contract| {
+            b.push_bind(&contract.id)
+                .push_bind(contract.accepted)
+                .push_bind(contract.fulfilled)
+                .push_bind(&contract.terms.deadline)
+                .push_unseparated("::timestamptz");
+        });
+        query_builder.push(" ON CONFLICT (id) DO UPDATE SET accepted = EXCLUDED.accepted, fulfilled = EXCLUDED.fulfilled,
+            deadline = EXCLUDED.deadline, expired = false");
+        query_builder.build().execute(&mut transaction).await?;
+    }
+
+    let ids: Vec<&str> = contracts.iter().map(|contract| contract.id.as_str()).collect();
+    let expired = sqlx::query("UPDATE contracts SET expired = true WHERE NOT expired AND id <> ALL($1)")
+        .bind(&ids)
+        .execute(&mut transaction)
+        .await?
+        .rows_affected();
+
+    transaction.commit().await?;
+    Ok(expired)
+}
+
+/// Refreshes the contracts table from the API.
+#[tracing::instrument(skip_all)]
+async fn sync_contracts (ctx: &Context) -> Result<(), sqlx::Error> {
+    tracing::info!("syncing contracts");
+    match st_util::list_contracts(ctx).await {
+        Ok(contracts) => {
+            let expired = upsert_contracts(ctx, &contracts).await?;
+            tracing::info!(rows = contracts.len(), expired, "contracts synced");
+        }
+        Err(err) => tracing::error!(error = %describe_api_error(&err), "contracts sync failed"),
+    }
+    Ok(())
+}
+
+/// Updates the local copy of a contract after it changed through the API.
+async fn update_contract_row (ctx: &Context, contract : &Contract) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE contracts SET accepted = $2, fulfilled = $3, deadline = $4::timestamptz WHERE id = $1")
+        .bind(&contract.id)
+        .bind(contract.accepted)
+        .bind(contract.fulfilled)
+        .bind(&contract.terms.deadline)
+        .execute(&ctx.db_pool)
+        .await?;
+    Ok(())
+}
+
+/// Default age after which systems data is re-fetched, unless overridden by `SYSTEMS_MAX_AGE_HOURS`.
+const DEFAULT_SYSTEMS_MAX_AGE_HOURS: i32 = 24;
+
+/// Fetches all systems from the API and upserts them and their waypoints, recording when this happened.
+async fn refresh_systems_data (ctx: &Context) -> Result<(), AppError> {
+    let systems = spacedust::apis::systems_api::get_systems_all(&ctx.configuration).await
+        .map_err(|err| AppError::Api(describe_api_error(&err)))?;
+    upsert_systems(ctx, &systems).await?;
+    upsert_waypoints(ctx, &systems).await?;
+
+    sqlx::query("INSERT INTO systems_meta(key, value)
+                VALUES ('last_systems_fetch', to_char(NOW() AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"'))
+                ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value")
+        .execute(&ctx.db_pool)
+        .await?;
+    Ok(())
+}
+
+/// Replaces the systems and waypoints tables with fresh data from the API.
+/// Used after a server reset, when none of the stored systems exist any more.
+/// The old tables are archived first, for `ResetDelta` to compare against.
+async fn rebuild_systems_data (ctx: &Context) -> Result<(), AppError> {
+    let mut transaction = ctx.db_pool.begin().await?;
+    sqlx::query("DELETE FROM systems_archive").execute(&mut transaction).await?;
+    sqlx::query("DELETE FROM waypoints_archive").execute(&mut transaction).await?;
+    sqlx::query("INSERT INTO systems_archive SELECT * FROM systems").execute(&mut transaction).await?;
+    sqlx::query("INSERT INTO waypoints_archive SELECT * FROM waypoints").execute(&mut transaction).await?;
+    sqlx::query("INSERT INTO systems_meta(key, value) VALUES ('last_archive', NOW()::text)
+                ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value")
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query("DELETE FROM waypoint_traits").execute(&mut transaction).await?;
+    sqlx::query("DELETE FROM waypoints").execute(&mut transaction).await?;
+    sqlx::query("DELETE FROM systems").execute(&mut transaction).await?;
+    transaction.commit().await?;
+    ctx.query_cache.invalidate_waypoints();
+    ctx.query_cache.invalidate_systems();
+
+    refresh_systems_data(ctx).await?;
+    seed_waypoint_traits(ctx).await?;
+    Ok(())
+}
+
+/// Whether the stored systems data was fetched more than `SYSTEMS_MAX_AGE_HOURS` ago, or has no recorded fetch time.
+async fn systems_data_is_stale (ctx: &Context) -> Result<bool, sqlx::Error> {
+    let max_age_hours = context::parse_env("SYSTEMS_MAX_AGE_HOURS", DEFAULT_SYSTEMS_MAX_AGE_HOURS);
+    let fresh: Option<bool> = sqlx::query_scalar(
+        "SELECT value::timestamptz > NOW() - make_interval(hours => $1) FROM systems_meta WHERE key = 'last_systems_fetch'")
+        .bind(max_age_hours)
+        .fetch_optional(&ctx.db_pool)
+        .await?;
+    Ok(fresh != Some(true))
+}
+
+async fn table_is_empty (ctx: &Context, table: &str) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query(&format!("SELECT FROM {table} LIMIT 1"))
+        .execute(&ctx.db_pool)
+        .await?
+        .rows_affected() == 0)
+}
+
+/// Fetches systems data from the API if it isn't cached yet or is older than `SYSTEMS_MAX_AGE_HOURS`.
+async fn ensure_systems_data (ctx: &Context) -> Result<(), AppError> {
+    if table_is_empty(ctx, "systems").await? || table_is_empty(ctx, "waypoints").await? || systems_data_is_stale(ctx).await? {
+        refresh_systems_data(ctx).await?;
+    }
+    if table_is_empty(ctx, "waypoint_traits").await? {
+        seed_waypoint_traits(ctx).await?;
+    }
+
+    // Contracts change often, so they are re-fetched every time.
+    sync_contracts(ctx).await?;
+    Ok(())
+}
+
+
+//----------------------------------------------------------------------
+//                        BACKGROUND TASKS
+//----------------------------------------------------------------------
+
+/// Default interval between server status polls, unless overridden by `STATUS_POLL_SECS`.
+const DEFAULT_STATUS_POLL_SECS: u64 = 300;
 
-    if !systems_exists || !waypoints_exists {
-        let systems = spacedust::apis::systems_api::get_systems_all(&CONFIGURATION).await.expect("Get all systems");
-        create_systems_table(&systems).await;
-        create_waypoints_table(&systems).await;
-        
+/// Records a game event, returning whether it had not been seen before.
+async fn record_game_event (ctx: &Context, kind: &str, title: &str, body: &str) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query("INSERT INTO game_events(kind, title, body) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING")
+        .bind(kind)
+        .bind(title)
+        .bind(body)
+        .execute(&ctx.db_pool)
+        .await?
+        .rows_affected() > 0)
+}
+
+/// Stores announcements and server resets from the status endpoint, notifying about new ones.
+/// A new reset date means the universe was regenerated, so the systems data is rebuilt.
+async fn poll_game_status (ctx: &Context) -> Result<(), AppError> {
+    let status = match st_util::get_status(ctx).await {
+        Ok(status) => status,
+        Err(err) => {
+            println!("Error fetching server status: {}", describe_api_error(&err));
+            return Ok(());
+        }
+    };
+
+    for announcement in status["announcements"].as_array().into_iter().flatten() {
+        let title = announcement["title"].as_str().unwrap_or_default();
+        let body = announcement["body"].as_str().unwrap_or_default();
+        if record_game_event(ctx, "announcement", title, body).await? {
+            println!("\nNew announcement: {title}\n{body}");
+        }
+    }
+
+    if let Some(next_reset) = status["serverResets"]["next"].as_str() {
+        let frequency = status["serverResets"]["frequency"].as_str().unwrap_or_default();
+        record_game_event(ctx, "next_reset", next_reset, frequency).await?;
     }
 
+    if let Some(reset_date) = status["resetDate"].as_str() {
+        let (previous_resets,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM game_events WHERE kind = 'reset'")
+            .fetch_one(&ctx.db_pool)
+            .await?;
+        if record_game_event(ctx, "reset", reset_date, "").await? && previous_resets > 0 {
+            println!("\nServer was reset on {reset_date}, rebuilding systems data");
+            rebuild_systems_data(ctx).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Polls the server status every `STATUS_POLL_SECS` seconds.
+fn start_status_poll_task (ctx: &Context) -> tokio::task::JoinHandle<()> {
+    let interval = context::parse_env("STATUS_POLL_SECS", DEFAULT_STATUS_POLL_SECS);
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            interval.tick().await;
+            if let Err(err) = poll_game_status(&ctx).await {
+                tracing::error!(error = %err, "server status poll failed");
+            }
+        }
+    })
 }
 
 
@@ -174,126 +470,3231 @@ async fn ensure_systems_data () {
 //                            UTILITY
 //----------------------------------------------------------------------
 
-fn prompt_waypoint_symbol() -> String {
-    Text::new("Enter waypoint symbol").prompt().expect("Prompt error")
+static SYSTEM_SYMBOL_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Z0-9]+-[A-Z0-9]+$").expect("Valid regex"));
+static WAYPOINT_SYMBOL_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Z0-9]+-[A-Z0-9]+-[A-Z0-9]+$").expect("Valid regex"));
+static SHIP_SYMBOL_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Z0-9_-]+-[0-9A-F]+$").expect("Valid regex"));
+
+/// Checks that `s` looks like a system symbol, e.g. `X1-DF55`.
+fn validate_system_symbol(s: &str) -> Result<(), String> {
+    if SYSTEM_SYMBOL_REGEX.is_match(s) {
+        return Ok(());
+    }
+    if s.chars().any(char::is_lowercase) {
+        return Err("Symbols are uppercase".to_string());
+    }
+    Err(format!("Expected SECTOR-SYSTEM (e.g. X1-DF55), got {} segment(s)", s.split('-').count()))
 }
 
-fn prompt_system_symbol() -> String {
-    Text::new("Enter system symbol").prompt().expect("Prompt error")
+/// Checks that `s` looks like a waypoint symbol, e.g. `X1-DF55-20250Z`.
+fn validate_waypoint_symbol(s: &str) -> Result<(), String> {
+    if WAYPOINT_SYMBOL_REGEX.is_match(s) {
+        return Ok(());
+    }
+    if s.chars().any(char::is_lowercase) {
+        return Err("Symbols are uppercase".to_string());
+    }
+    Err(format!("Expected SECTOR-SYSTEM-WAYPOINT (e.g. X1-DF55-20250Z), got {} segment(s)", s.split('-').count()))
 }
 
-async fn system_symbol_from_waypoint_symbol(waypoint_symbol: &str) -> String {
-    let (system_symbol,): (String,) = sqlx::query_as("SELECT system_symbol FROM waypoints WHERE symbol = $1")
-        .bind(waypoint_symbol)
-        .fetch_one(get_global_db_pool().await)
-        .await
-        .expect("System symbol fetching");
-    system_symbol
+/// Checks that `s` looks like a ship symbol: the agent symbol and a hexadecimal number, e.g. `MYAGENT-1A`.
+fn validate_ship_symbol(s: &str) -> Result<(), String> {
+    if SHIP_SYMBOL_REGEX.is_match(s) {
+        return Ok(());
+    }
+    if s.chars().any(char::is_lowercase) {
+        return Err("Symbols are uppercase".to_string());
+    }
+    Err("Expected AGENT-NUMBER (e.g. MYAGENT-1A)".to_string())
+}
+
+/// Adapts a symbol validation function for use as an [`inquire`] validator.
+fn symbol_validator(validate: fn(&str) -> Result<(), String>) -> impl Fn(&str) -> Result<Validation, CustomUserError> + Clone {
+    move |input: &str| Ok(match validate(input) {
+        Ok(()) => Validation::Valid,
+        Err(message) => Validation::Invalid(message.into()),
+    })
+}
+
+fn prompt_waypoint_symbol() -> InquireResult<String> {
+    Text::new("Enter waypoint symbol")
+        .with_validator(symbol_validator(validate_waypoint_symbol))
+        .prompt()
+}
+
+/// Where a destination typed by the user points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ResolvedDestination {
+    Waypoint(String),
+    System(String),
+}
+
+impl std::fmt::Display for ResolvedDestination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolvedDestination::Waypoint(symbol) => write!(f, "waypoint {symbol}"),
+            ResolvedDestination::System(symbol) => write!(f, "system {symbol}"),
+        }
+    }
+}
+
+/// Why a destination couldn't be resolved.
+#[derive(Debug)]
+enum DestinationError {
+    Ambiguous { input: String, candidates: Vec<ResolvedDestination> },
+    NotFound { input: String, suggestions: Vec<String> },
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for DestinationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DestinationError::Ambiguous { input, candidates } => {
+                let candidates: Vec<String> = candidates.iter().map(ToString::to_string).collect();
+                write!(f, "\"{input}\" could be {}", candidates.join(" or "))
+            }
+            DestinationError::NotFound { input, suggestions } if suggestions.is_empty() => write!(f, "No known waypoint or system matches \"{input}\""),
+            DestinationError::NotFound { input, suggestions } => write!(f, "No known waypoint or system matches \"{input}\". Did you mean {}?", suggestions.join(", ")),
+            DestinationError::Database(err) => write!(f, "Error looking up destination: {err}"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for DestinationError {
+    fn from(err: sqlx::Error) -> Self {
+        DestinationError::Database(err)
+    }
+}
+
+/// Number of close matches suggested when a destination isn't found.
+const DESTINATION_SUGGESTIONS: i64 = 5;
+
+/// Works out what a destination typed by the user refers to: a waypoint symbol, a system symbol, or a system nickname.
+/// A well-formed waypoint symbol that isn't stored yet is accepted as-is, since the API may still know it.
+///
+/// # Errors
+/// Returns `Ambiguous` if the input matches more than one of these, `NotFound` with similar names if it matches none,
+/// and `Database` if a lookup fails
+async fn resolve_destination_input(ctx: &Context, input: &str) -> Result<ResolvedDestination, DestinationError> {
+    let input = input.trim();
+    let symbol = input.to_uppercase();
+
+    let mut candidates = Vec::new();
+    if ctx.query_cache.waypoint(&ctx.db_pool, &symbol).await?.is_some() {
+        candidates.push(ResolvedDestination::Waypoint(symbol.clone()));
+    }
+    if ctx.query_cache.system(&ctx.db_pool, &symbol).await?.is_some() {
+        candidates.push(ResolvedDestination::System(symbol.clone()));
+    }
+    let nicknamed: Option<String> = sqlx::query_scalar("SELECT system_symbol FROM system_nicknames WHERE lower(nickname) = lower($1)")
+        .bind(input)
+        .fetch_optional(&ctx.db_pool)
+        .await?;
+    if let Some(system_symbol) = nicknamed {
+        let nicknamed = ResolvedDestination::System(system_symbol);
+        if !candidates.contains(&nicknamed) {
+            candidates.push(nicknamed);
+        }
+    }
+
+    match candidates.len() {
+        1 => return Ok(candidates.remove(0)),
+        0 if WAYPOINT_SYMBOL_REGEX.is_match(&symbol) => return Ok(ResolvedDestination::Waypoint(symbol)),
+        0 => {}
+        _ => return Err(DestinationError::Ambiguous { input: input.to_string(), candidates }),
+    }
+
+    let suggestions: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM (
+            SELECT symbol AS name FROM waypoints WHERE left(symbol, length($1)) = $1
+            UNION SELECT symbol FROM systems WHERE left(symbol, length($1)) = $1
+            UNION SELECT nickname FROM system_nicknames WHERE lower(left(nickname, length($2))) = lower($2)
+        ) names ORDER BY name LIMIT $3")
+        .bind(&symbol)
+        .bind(input)
+        .bind(DESTINATION_SUGGESTIONS)
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    Err(DestinationError::NotFound { input: input.to_string(), suggestions })
+}
+
+/// Prompts for a destination until it resolves to a waypoint, asking which waypoint when a system is given.
+/// Returns `None` if the user skips the prompt.
+async fn prompt_destination(ctx: &Context) -> Result<Option<String>, AppError> {
+    loop {
+        let Some(input) = Text::new("Enter destination")
+            .with_help_message("Waypoint symbol, system symbol or system nickname")
+            .prompt_skippable()? else {
+            return Ok(None);
+        };
+        let system_symbol = match resolve_destination_input(ctx, &input).await {
+            Ok(ResolvedDestination::Waypoint(symbol)) => return Ok(Some(symbol)),
+            Ok(ResolvedDestination::System(symbol)) => symbol,
+            Err(DestinationError::Database(err)) => return Err(err.into()),
+            Err(err) => {
+                println!("{err}");
+                continue;
+            }
+        };
+
+        let mut waypoints: Vec<(String, String)> = sqlx::query_as(
+            "SELECT symbol, COALESCE(type, '?') FROM waypoints WHERE system_symbol = $1 ORDER BY symbol")
+            .bind(&system_symbol)
+            .fetch_all(&ctx.db_pool)
+            .await?;
+        if waypoints.is_empty() {
+            println!("No waypoints stored for {system_symbol}");
+            continue;
+        }
+        let options: Vec<String> = waypoints.iter()
+            .map(|(symbol, waypoint_type)| format!("{} {symbol} ({waypoint_type})", waypoint_type_name_icon(waypoint_type)))
+            .collect();
+        let choice = Select::new(&format!("Waypoint in {system_symbol}"), options).raw_prompt()?;
+        return Ok(Some(waypoints.swap_remove(choice.index).0));
+    }
+}
+
+/// Turns a system nickname into its symbol. Anything that isn't a known nickname is returned unchanged.
+async fn resolve_system_symbol(ctx: &Context, input: &str) -> Result<String, sqlx::Error> {
+    Ok(sqlx::query_scalar("SELECT system_symbol FROM system_nicknames WHERE lower(nickname) = lower($1)")
+        .bind(input)
+        .fetch_optional(&ctx.db_pool)
+        .await?
+        .unwrap_or_else(|| input.to_string()))
+}
+
+/// Offers bookmarked systems as quick-select options before falling back to free text,
+/// which may be a symbol or a system nickname.
+async fn prompt_system_symbol(ctx: &Context) -> Result<String, AppError> {
+    let bookmarks: Vec<(String, Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT b.system_symbol, b.label, n.nickname FROM system_bookmarks b
+        LEFT JOIN system_nicknames n ON n.system_symbol = b.system_symbol
+        ORDER BY b.system_symbol"
+        )
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    if !bookmarks.is_empty() {
+        let mut options: Vec<String> = bookmarks.iter()
+            .map(|(symbol, label, nickname)| {
+                let name = nickname.as_ref().map_or_else(|| symbol.clone(), |nickname| format!("{symbol} \"{nickname}\""));
+                match label {
+                    Some(label) => format!("{name} ({label})"),
+                    None => name,
+                }
+            })
+            .collect();
+        options.push("Other...".to_string());
+
+        let choice = Select::new("Select system", options).raw_prompt()?;
+        if let Some((symbol, ..)) = bookmarks.get(choice.index) {
+            return Ok(symbol.clone());
+        }
+    }
+
+    let nicknames: Vec<String> = sqlx::query_scalar("SELECT lower(nickname) FROM system_nicknames")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    let input = Text::new("Enter system symbol or nickname")
+        .with_validator(move |input: &str| -> Result<Validation, CustomUserError> {
+            if nicknames.contains(&input.to_lowercase()) {
+                return Ok(Validation::Valid);
+            }
+            Ok(match validate_system_symbol(input) {
+                Ok(()) => Validation::Valid,
+                Err(message) => Validation::Invalid(message.into()),
+            })
+        })
+        .prompt()?;
+    Ok(resolve_system_symbol(ctx, &input).await?)
+}
+
+/// Prompts for a contract from the contracts table matching the given status.
+/// Returns `None` if there is no such contract.
+async fn prompt_contract_id(ctx: &Context, accepted: bool, fulfilled: bool) -> Result<Option<String>, AppError> {
+    let contracts: Vec<(String, String)> = sqlx::query_as(
+        "SELECT id, to_char(deadline, 'YYYY-MM-DD HH24:MI') FROM contracts WHERE accepted = $1 AND fulfilled = $2 AND NOT expired ORDER BY deadline"
+        )
+        .bind(accepted)
+        .bind(fulfilled)
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    if contracts.is_empty() {
+        return Ok(None);
+    }
+
+    let options: Vec<String> = contracts.iter()
+        .map(|(id, deadline)| format!("{id} (deadline {deadline})"))
+        .collect();
+    let choice = Select::new("Select contract", options).raw_prompt()?;
+    Ok(contracts.into_iter().nth(choice.index).map(|(id, _)| id))
+}
+
+/// Prompts for one of your ships, labelled with its role, location and nav status.
+/// Returns `None` if there are no ships to choose from.
+async fn prompt_ship(ctx: &Context) -> InquireResult<Option<Ship>> {
+    let ships = match st_util::list_ships(ctx).await {
+        Ok(ships) => ships,
+        Err(err) => {
+            println!("Error listing ships: {}", describe_api_error(&err));
+            return Ok(None);
+        }
+    };
+    if ships.is_empty() {
+        println!("No ships");
+        return Ok(None);
+    }
+
+    let options: Vec<String> = ships.iter().map(ship_option_label).collect();
+    let choice = Select::new("Select ship", options).raw_prompt()?;
+    Ok(ships.into_iter().nth(choice.index))
+}
+
+/// Label for a ship in selection prompts, showing its role, location and status.
+fn ship_option_label(ship: &Ship) -> String {
+    format!(
+        "{} ({} at {} ({}), {})",
+        ship.symbol,
+        ship.registration.role.to_string(),
+        ship.nav.waypoint_symbol,
+        st_util::decode_waypoint_symbol(&ship.nav.waypoint_symbol),
+        ship.nav.status.to_string()
+    )
 }
 
 //----------------------------------------------------------------------
 //                          MENU CHOICES
 //----------------------------------------------------------------------
 
-#[derive(Debug, EnumIter, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter)]
 enum MenuChoice {
     GetAgent,
     ListContracts,
+    SyncContracts,
+    ViewContractDeliveryStatus,
     ListShips,
     ListWaypoints,
     GetWaypoint,
+    SearchWaypointsByTrait,
+    FindNearestMarketplace,
+    BuildJumpGateGraph,
+    FindRoute,
+    PopulateDistanceCache,
+    CheckDataIntegrity,
+    BookmarkSystem,
+    ListBookmarks,
+    NicknameSystem,
+    FactionMap,
+    FleetSpread,
+    ResetDelta,
+    EconomicZoneAnalysis,
+    ProductionChain,
+    DatabaseSize,
+    ExportCSV,
+    ImportCSV,
+    GameNews,
+    ShipStatus,
+    NavigateShip,
+    OrbitDock,
+    FormationNavigate,
+    RefuelShip,
+    SurveyWaypoint,
+    ExtractResources,
+    TransferCargo,
+    PurchaseShip,
+    AcceptContract,
+    FulfillContract,
+    BuyGoods,
+    SellGoods,
+    UpdateMarketPrices,
+    RefreshNearbyMarkets,
+    FindBestTrade,
+    ListWaypointMarkets,
+    ManageWatchlist,
+    WatchlistPrices,
+    ManageReserves,
+    ToggleStatusBar,
+    Help,
     Exit
 }
 
-async fn get_agent() {
-    if let Ok(res) = spacedust::apis::agents_api::get_my_agent(&CONFIGURATION).await {
-        println!("{:#?}", *(res.data));
+impl std::fmt::Display for MenuChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            MenuChoice::GetAgent => "Get Agent Info",
+            MenuChoice::ListContracts => "List All Contracts",
+            MenuChoice::SyncContracts => "Sync Contracts from API",
+            MenuChoice::ViewContractDeliveryStatus => "View Contract Delivery Status",
+            MenuChoice::ListShips => "List All Ships",
+            MenuChoice::ListWaypoints => "List Waypoints in System",
+            MenuChoice::GetWaypoint => "Get Waypoint Details",
+            MenuChoice::SearchWaypointsByTrait => "Search Waypoints by Trait",
+            MenuChoice::FindNearestMarketplace => "Find Nearest Marketplaces",
+            MenuChoice::BuildJumpGateGraph => "Build Jump Gate Graph",
+            MenuChoice::FindRoute => "Find Jump Route Between Systems",
+            MenuChoice::PopulateDistanceCache => "Populate System Distance Cache",
+            MenuChoice::CheckDataIntegrity => "Check Data Integrity",
+            MenuChoice::BookmarkSystem => "Bookmark a System",
+            MenuChoice::ListBookmarks => "List Bookmarked Systems",
+            MenuChoice::NicknameSystem => "Nickname a System",
+            MenuChoice::FactionMap => "Show Faction Territory Map",
+            MenuChoice::FleetSpread => "Show Fleet Spread Map",
+            MenuChoice::ResetDelta => "Compare Universe to Before Last Reset",
+            MenuChoice::EconomicZoneAnalysis => "Analyze Economic Zone Around Waypoint",
+            MenuChoice::ProductionChain => "Show Production Chain in System",
+            MenuChoice::DatabaseSize => "Show Database Size by Table",
+            MenuChoice::ExportCSV => "Export Systems and Waypoints to CSV",
+            MenuChoice::ImportCSV => "Import Systems and Waypoints from CSV",
+            MenuChoice::GameNews => "Show Game News",
+            MenuChoice::ShipStatus => "Show Fleet Status",
+            MenuChoice::NavigateShip => "Navigate Ship to Waypoint",
+            MenuChoice::OrbitDock => "Toggle Ship Orbit/Dock",
+            MenuChoice::FormationNavigate => "Navigate Ships in Formation",
+            MenuChoice::RefuelShip => "Refuel Ship",
+            MenuChoice::SurveyWaypoint => "Survey Waypoint",
+            MenuChoice::ExtractResources => "Extract Resources",
+            MenuChoice::TransferCargo => "Transfer Cargo Between Ships",
+            MenuChoice::PurchaseShip => "Purchase Ship",
+            MenuChoice::AcceptContract => "Accept a Contract",
+            MenuChoice::FulfillContract => "Fulfill a Contract",
+            MenuChoice::BuyGoods => "Buy Goods at Market",
+            MenuChoice::SellGoods => "Sell Goods at Market",
+            MenuChoice::UpdateMarketPrices => "Update Market Prices Where Ships Are",
+            MenuChoice::RefreshNearbyMarkets => "Refresh Markets Near Waypoint",
+            MenuChoice::FindBestTrade => "Find Best Trades from Recorded Prices",
+            MenuChoice::ListWaypointMarkets => "List Known Market Prices in System",
+            MenuChoice::ManageWatchlist => "Manage Trade Watchlist",
+            MenuChoice::WatchlistPrices => "Show Watchlist Prices",
+            MenuChoice::ManageReserves => "Manage Cargo Reserves",
+            MenuChoice::ToggleStatusBar => "Toggle Status Bar",
+            MenuChoice::Help => "Help: Ask What to Do",
+            MenuChoice::Exit => "Exit",
+        };
+        f.write_str(description)
     }
+}
 
-    match spacedust::apis::agents_api::get_my_agent(&CONFIGURATION).await {
-        Ok(res) => {
-            println!("{:#?}", *(res.data));
-        }
-        Err(err_res) => {
-            println!("{err_res:#?}");
+/// Menu categories, in display order.
+const MENU_CATEGORIES: &[&str] = &["Fleet", "Trading", "Exploration", "Database", "Analysis", "Settings", "General"];
+
+/// Category used to group related choices in the main menu.
+fn menu_choice_category(choice: &MenuChoice) -> &'static str {
+    match choice {
+        MenuChoice::GetAgent
+        | MenuChoice::ListShips
+        | MenuChoice::ShipStatus
+        | MenuChoice::NavigateShip
+        | MenuChoice::OrbitDock
+        | MenuChoice::FormationNavigate
+        | MenuChoice::RefuelShip
+        | MenuChoice::SurveyWaypoint
+        | MenuChoice::ExtractResources
+        | MenuChoice::TransferCargo
+        | MenuChoice::PurchaseShip => "Fleet",
+        MenuChoice::ListContracts
+        | MenuChoice::SyncContracts
+        | MenuChoice::ViewContractDeliveryStatus
+        | MenuChoice::AcceptContract
+        | MenuChoice::FulfillContract
+        | MenuChoice::BuyGoods
+        | MenuChoice::SellGoods
+        | MenuChoice::UpdateMarketPrices
+        | MenuChoice::RefreshNearbyMarkets
+        | MenuChoice::FindBestTrade
+        | MenuChoice::ListWaypointMarkets
+        | MenuChoice::ManageWatchlist
+        | MenuChoice::WatchlistPrices
+        | MenuChoice::ManageReserves => "Trading",
+        MenuChoice::ListWaypoints
+        | MenuChoice::GetWaypoint
+        | MenuChoice::SearchWaypointsByTrait
+        | MenuChoice::FindNearestMarketplace
+        | MenuChoice::BuildJumpGateGraph
+        | MenuChoice::FindRoute
+        | MenuChoice::PopulateDistanceCache
+        | MenuChoice::BookmarkSystem
+        | MenuChoice::ListBookmarks
+        | MenuChoice::NicknameSystem => "Exploration",
+        MenuChoice::CheckDataIntegrity
+        | MenuChoice::DatabaseSize
+        | MenuChoice::ExportCSV
+        | MenuChoice::ImportCSV => "Database",
+        MenuChoice::FactionMap | MenuChoice::FleetSpread | MenuChoice::ResetDelta | MenuChoice::EconomicZoneAnalysis | MenuChoice::ProductionChain => "Analysis",
+        MenuChoice::ToggleStatusBar => "Settings",
+        MenuChoice::GameNews | MenuChoice::Help | MenuChoice::Exit => "General",
+    }
+}
+
+/// Entries of the top level of the main menu.
+/// Choices in the `General` category are listed directly instead of in a sub-menu.
+enum TopLevelChoice {
+    Category(&'static str),
+    ShowAll,
+    Choice(MenuChoice),
+}
+
+impl std::fmt::Display for TopLevelChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TopLevelChoice::Category(category) => write!(f, "{category}..."),
+            TopLevelChoice::ShowAll => f.write_str("Show All..."),
+            TopLevelChoice::Choice(choice) => write!(f, "{choice}"),
         }
     }
 }
 
-async fn list_contracts() {
-    match st_util::list_contracts().await {
-        Ok(contracts) => {
-            for contract in contracts {
-                println!("{contract:#?}");
-            }
+/// Prompts for a category, then for an action within it.
+/// Returns `None` if the user backed out of a sub-menu.
+fn prompt_main_menu() -> InquireResult<Option<MenuChoice>> {
+    let mut top_level: Vec<TopLevelChoice> = MENU_CATEGORIES.iter()
+        .filter(|category| **category != "General")
+        .filter(|category| MenuChoice::iter().any(|choice| menu_choice_category(&choice) == **category))
+        .map(|category| TopLevelChoice::Category(category))
+        .collect();
+    top_level.push(TopLevelChoice::ShowAll);
+    top_level.extend(MenuChoice::iter()
+        .filter(|choice| menu_choice_category(choice) == "General")
+        .map(TopLevelChoice::Choice));
+
+    let choices: Vec<MenuChoice> = match Select::new("Main Menu", top_level).prompt()? {
+        TopLevelChoice::Choice(choice) => return Ok(Some(choice)),
+        TopLevelChoice::Category(category) => MenuChoice::iter()
+            .filter(|choice| menu_choice_category(choice) == category)
+            .collect(),
+        TopLevelChoice::ShowAll => {
+            let mut choices: Vec<MenuChoice> = MenuChoice::iter().collect();
+            choices.sort_by_key(|choice| MENU_CATEGORIES.iter().position(|c| *c == menu_choice_category(choice)));
+            choices
         }
-        Err(err) => println!("Error listing contracts: {err:#?}")
+    };
+
+    Select::new("Select action", choices)
+        .with_help_message("↑↓ to move, enter to select, esc to go back")
+        .prompt_skippable()
+}
+
+/// Ships below this fraction of their fuel capacity are flagged in the status bar.
+const LOW_FUEL_FRACTION: f64 = 0.25;
+
+/// Contracts due within this many hours are counted as urgent in the status bar.
+const URGENT_CONTRACT_HOURS: i32 = 24;
+
+/// Formats credits compactly, e.g. `5.2M` or `45K`.
+fn format_credits(credits: i64) -> String {
+    let magnitude = credits.unsigned_abs() as f64;
+    let sign = if credits < 0 { "-" } else { "" };
+    if magnitude >= 1_000_000.0 {
+        format!("{sign}{:.1}M", magnitude / 1_000_000.0)
+    } else if magnitude >= 1_000.0 {
+        format!("{sign}{:.0}K", magnitude / 1_000.0)
+    } else {
+        credits.to_string()
     }
 }
 
-async fn list_ships() {
-    match st_util::list_ships().await {
+/// [`cache::ApiCache`] keys of the agent's credits and ship list shown in the status bar.
+const CREDITS_CACHE_KEY: &str = "agent_credits";
+const SHIPS_CACHE_KEY: &str = "ships";
+
+/// Drops the status bar's cached credits and ships. Called by actions that change either,
+/// so the status bar shown after them is fetched fresh.
+fn invalidate_status_bar(ctx: &Context) {
+    ctx.api_cache.invalidate(CREDITS_CACHE_KEY);
+    ctx.api_cache.invalidate(SHIPS_CACHE_KEY);
+}
+
+/// The agent's credits and their change since the start of the day.
+/// Credits are fetched from the API at most once per [`cache::ApiCache`] TTL, and each fresh value is recorded in `credit_history`.
+async fn status_bar_credits(ctx: &Context) -> Result<(i64, i64), String> {
+    let credits = if let Some(credits) = ctx.api_cache.get::<i64>(CREDITS_CACHE_KEY) {
+        credits
+    } else {
+        let res = spacedust::apis::agents_api::get_my_agent(&ctx.configuration).await.map_err(|err| describe_api_error(&err))?;
+        let credits = i64::from(res.data.credits);
+        ctx.api_cache.insert(CREDITS_CACHE_KEY.to_string(), credits);
+        sqlx::query("INSERT INTO credit_history(credits) VALUES ($1) ON CONFLICT DO NOTHING")
+            .bind(credits)
+            .execute(&ctx.db_pool)
+            .await
+            .map_err(|err| err.to_string())?;
+        credits
+    };
+    let start_of_day: Option<i64> = sqlx::query_scalar(
+        "SELECT credits FROM credit_history WHERE observed_at >= date_trunc('day', NOW()) ORDER BY observed_at LIMIT 1")
+        .fetch_optional(&ctx.db_pool)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok((credits, credits - start_of_day.unwrap_or(credits)))
+}
+
+/// Prints a one-line summary of ships, contracts and credits, shown before the menu when enabled.
+/// Ship and agent data come from the API cache, so rendering the menu repeatedly doesn't spend API calls.
+/// A part that can't be loaded shows its error instead.
+async fn print_status_bar(ctx: &Context) {
+    let mut parts = Vec::new();
+
+    match ctx.api_cache.get_or_fetch(SHIPS_CACHE_KEY.to_string(), || st_util::list_ships(ctx)).await {
         Ok(ships) => {
-            for ship in ships {
-                println!("{ship:#?}");
+            let docked = ships.iter().filter(|ship| ship.nav.status == ShipNavStatus::Docked).count();
+            let in_transit = ships.iter().filter(|ship| ship.nav.status == ShipNavStatus::InTransit).count();
+            let orbiting = ships.len() - docked - in_transit;
+            parts.push(format!("⚓ Ships: {docked} docked, {orbiting} orbit, {in_transit} transit"));
+            let low_fuel = ships.iter()
+                .filter(|ship| ship.fuel.capacity > 0 && f64::from(ship.fuel.current) < f64::from(ship.fuel.capacity) * LOW_FUEL_FRACTION)
+                .count();
+            if low_fuel > 0 {
+                parts.push(format!("⚠️ {low_fuel} ship(s) low fuel"));
             }
         }
-        Err(err) => println!("Error listing contracts: {err:#?}")
+        Err(err) => parts.push(format!("Ships: {}", describe_api_error(&err))),
     }
-}
 
-//TODO: have this populate more of the database with whatever useful information
-async fn list_waypoints() {
-    let system_symbol = &prompt_system_symbol();
+    let contracts: Result<(i64, i64), sqlx::Error> = sqlx::query_as(
+        "SELECT COUNT(*), COUNT(*) FILTER (WHERE deadline < NOW() + make_interval(hours => $1))
+        FROM contracts WHERE accepted AND NOT fulfilled AND NOT expired")
+        .bind(URGENT_CONTRACT_HOURS)
+        .fetch_one(&ctx.db_pool)
+        .await;
+    match contracts {
+        Ok((active, urgent)) => parts.push(format!("📜 Contracts: {active} active ({urgent} urgent)")),
+        Err(err) => parts.push(format!("Contracts: {err}")),
+    }
 
-    match st_util::list_system_waypoints(system_symbol).await {
-        Ok(waypoints) => {
-            for waypoint in waypoints {
-                println!("{waypoint:#?}");
-            }
+    match status_bar_credits(ctx).await {
+        Ok((credits, change)) => {
+            let sign = if change < 0 { "-" } else { "+" };
+            parts.push(format!("💰 Credits: ¢{} ({sign}¢{} today)", format_credits(credits), format_credits(change.abs())));
         }
-        Err(err) => println!("Error listing waypoints: {err:#?}")
+        Err(err) => parts.push(format!("Credits: {err}")),
     }
 
+    println!("{}", parts.join(" | "));
+}
+
+/// Phrases understood by `Help`, with the action each one leads to.
+/// A phrase matches when every one of its words is in the question. Of equally specific matches, the earliest wins.
+const HELP_KEYWORDS: &[(&str, MenuChoice)] = &[
+    ("agent", MenuChoice::GetAgent),
+    ("credits", MenuChoice::GetAgent),
+    ("contracts", MenuChoice::ListContracts),
+    ("accept contract", MenuChoice::AcceptContract),
+    ("deliver contract", MenuChoice::ViewContractDeliveryStatus),
+    ("fulfill contract", MenuChoice::FulfillContract),
+    ("ships", MenuChoice::ShipStatus),
+    ("fleet", MenuChoice::ShipStatus),
+    ("where ships", MenuChoice::FleetSpread),
+    ("fly", MenuChoice::NavigateShip),
+    ("navigate", MenuChoice::NavigateShip),
+    ("move ship", MenuChoice::NavigateShip),
+    ("dock", MenuChoice::OrbitDock),
+    ("orbit", MenuChoice::OrbitDock),
+    ("refuel", MenuChoice::RefuelShip),
+    ("find fuel", MenuChoice::FindNearestMarketplace),
+    ("find market", MenuChoice::FindNearestMarketplace),
+    ("survey", MenuChoice::SurveyWaypoint),
+    ("mine", MenuChoice::ExtractResources),
+    ("extract", MenuChoice::ExtractResources),
+    ("transfer cargo", MenuChoice::TransferCargo),
+    ("buy ship", MenuChoice::PurchaseShip),
+    ("buy", MenuChoice::BuyGoods),
+    ("sell", MenuChoice::SellGoods),
+    ("sell cargo", MenuChoice::SellGoods),
+    ("prices", MenuChoice::ListWaypointMarkets),
+    ("update prices", MenuChoice::UpdateMarketPrices),
+    ("best trade", MenuChoice::FindBestTrade),
+    ("profit", MenuChoice::FindBestTrade),
+    ("watchlist", MenuChoice::ManageWatchlist),
+    ("reserve cargo", MenuChoice::ManageReserves),
+    ("waypoints", MenuChoice::ListWaypoints),
+    ("waypoint details", MenuChoice::GetWaypoint),
+    ("route", MenuChoice::FindRoute),
+    ("jump gate", MenuChoice::BuildJumpGateGraph),
+    ("bookmark", MenuChoice::BookmarkSystem),
+    ("nickname", MenuChoice::NicknameSystem),
+    ("factions", MenuChoice::FactionMap),
+    ("map", MenuChoice::FactionMap),
+    ("news", MenuChoice::GameNews),
+    ("export", MenuChoice::ExportCSV),
+    ("import", MenuChoice::ImportCSV),
+    ("quit", MenuChoice::Exit),
+];
+
+/// Finds the action best matching a question: the matching phrase with the most words (then the longest),
+/// or else the menu label sharing the most words.
+fn match_help_question(question: &str) -> Option<MenuChoice> {
+    let words: HashSet<String> = question.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase().trim_end_matches('s').to_string())
+        .collect();
+    let contains = |phrase: &str| phrase.split(' ').all(|word| words.contains(word.trim_end_matches('s')));
+
+    let keyword_match = HELP_KEYWORDS.iter()
+        .filter(|(phrase, _)| contains(phrase))
+        .min_by_key(|(phrase, _)| Reverse((phrase.split(' ').count(), phrase.len())))
+        .map(|(_, choice)| *choice);
+    keyword_match.or_else(|| MenuChoice::iter()
+        .filter(|choice| !matches!(choice, MenuChoice::Help))
+        .map(|choice| {
+            let shared = choice.to_string().split(' ').filter(|word| word.len() > 3 && contains(&word.to_lowercase())).count();
+            (shared, choice)
+        })
+        .filter(|(shared, _)| *shared > 0)
+        .max_by_key(|(shared, _)| *shared)
+        .map(|(_, choice)| choice))
 }
 
-async fn get_waypoint() {
-    let waypoint_symbol = prompt_waypoint_symbol();
-    let system_symbol = system_symbol_from_waypoint_symbol(&waypoint_symbol).await;
+/// Asks what the user wants to do, and offers the matching action.
+fn interactive_help() -> InquireResult<Option<MenuChoice>> {
+    let Some(question) = Text::new("What do you want to do?")
+        .with_placeholder("e.g. sell cargo, find fuel, buy ship")
+        .prompt_skippable()? else {
+        return Ok(None);
+    };
+    let Some(choice) = match_help_question(&question) else {
+        println!("No action matches \"{question}\". Try the menu categories instead.");
+        return Ok(None);
+    };
+    let run = Confirm::new(&format!("{choice} ({})?", menu_choice_category(&choice)))
+        .with_default(true)
+        .prompt()?;
+    Ok(run.then_some(choice))
+}
 
-    match spacedust::apis::systems_api::get_waypoint(&CONFIGURATION, &system_symbol, &waypoint_symbol).await {
+async fn get_agent(ctx: &Context) {
+    match spacedust::apis::agents_api::get_my_agent(&ctx.configuration).await {
         Ok(res) => {
-            println!("{:#?}", *(res.data));
+            let agent = &res.data;
+            tracing::info!(
+                symbol = %agent.symbol, credits = agent.credits, headquarters = %agent.headquarters, account_id = %agent.account_id,
+                "agent fetched"
+            );
         }
         Err(err_res) => {
-            println!("{err_res:#?}");
+            tracing::error!(error = %describe_api_error(&err_res), "agent fetch failed");
         }
     }
 }
 
-
-#[tokio::main]
-async fn main() {
-    //Setup
-    setup_dotenv();
-    ensure_systems_data().await;
-    
+/// Prints one page of results at a time with `show`, only fetching further pages when the user asks for them.
+async fn browse_paginated<T, E: Debug>(mut display: st_util::PaginatedDisplay<T, E>, what: &str, show: impl Fn(&[T])) {
+    let mut action = "Current page";
     loop {
-        match Select::new("Main Menu", MenuChoice::iter().collect()).prompt() {
+        let items = match action {
+            "Next page" => display.next_page().await,
+            "Previous page" => display.prev_page().await,
+            _ => display.current_page().await,
+        };
+        match items {
+            Ok(items) => show(items),
             Err(err) => {
-                println!("Prompt error! {err:#?}");
+                tracing::error!(error = ?err, "listing {what} failed");
+                return;
             }
-            Ok(choice) => match choice {
-                MenuChoice::GetAgent => get_agent().await,
-                MenuChoice::ListContracts => list_contracts().await,
-                MenuChoice::ListShips => list_ships().await,
-                MenuChoice::ListWaypoints => list_waypoints().await,
-                MenuChoice::GetWaypoint => get_waypoint().await,
-                MenuChoice::Exit => {
-                    println!("Bye!");
-                    break;
-                }
+        }
+
+        let total_pages = display.total_pages().map_or_else(|| "?".to_string(), |pages| pages.to_string());
+        println!("Page {} of {total_pages}", display.page() + 1);
+
+        let mut options = Vec::new();
+        if display.has_next_page() {
+            options.push("Next page");
+        }
+        if display.page() > 0 {
+            options.push("Previous page");
+        }
+        options.push("Done");
+        action = match Select::new("Navigate pages", options).prompt() {
+            Ok("Done") => return,
+            Ok(choice) => choice,
+            Err(err) => {
+                tracing::error!(error = %err, "prompt failed");
+                return;
             }
+        };
+    }
+}
+
+/// Prints a page of contracts as a table, followed by the progress of each delivery term.
+fn print_contract_summaries(contracts: &[st_util::ContractSummary]) {
+    println!("{:<28} {:<10} {:<12} {:<10} {:<26} {:>10}", "ID", "FACTION", "TYPE", "STATUS", "EXPIRATION", "REWARD");
+    for contract in contracts {
+        let status = if contract.fulfilled {
+            "FULFILLED"
+        } else if contract.accepted {
+            "ACCEPTED"
+        } else {
+            "OPEN"
+        };
+        println!(
+            "{:<28} {:<10} {:<12} {status:<10} {:<26} {:>10}",
+            contract.id, contract.faction, contract.type_name, contract.expiration, contract.reward
+        );
+        for delivery in &contract.deliveries_progress {
+            println!(
+                "    {} -> {}: {}/{} ({:.0}%)",
+                delivery.trade_symbol,
+                delivery.destination_symbol,
+                delivery.units_fulfilled,
+                delivery.units_required,
+                delivery.percent()
+            );
         }
     }
 }
+
+async fn list_contracts(ctx: &Context) {
+    browse_paginated(st_util::paginate_contracts(ctx), "contracts", print_contract_summaries).await;
+}
+
+/// Width of the bar drawn by `progress_bar`, excluding the brackets.
+const PROGRESS_BAR_WIDTH: usize = 20;
+
+/// Draws an ASCII progress bar such as `[====>     ]` for `done` out of `total`.
+fn progress_bar(done: i32, total: i32) -> String {
+    let fraction = if total <= 0 { 1.0 } else { (f64::from(done) / f64::from(total)).clamp(0.0, 1.0) };
+    let filled = (fraction * PROGRESS_BAR_WIDTH as f64).round() as usize;
+    let bar = if filled >= PROGRESS_BAR_WIDTH {
+        "=".repeat(PROGRESS_BAR_WIDTH)
+    } else if filled == 0 {
+        " ".repeat(PROGRESS_BAR_WIDTH)
+    } else {
+        format!("{}>{}", "=".repeat(filled - 1), " ".repeat(PROGRESS_BAR_WIDTH - filled))
+    };
+    format!("[{bar}]")
+}
+
+async fn view_contract_delivery_status(ctx: &Context) {
+    let contracts = match st_util::list_contracts(ctx).await {
+        Ok(contracts) => contracts,
+        Err(err) => {
+            println!("Error listing contracts: {}", describe_api_error(&err));
+            return;
+        }
+    };
+    let active: Vec<_> = contracts.iter().filter(|contract| contract.accepted && !contract.fulfilled).collect();
+    if active.is_empty() {
+        println!("No accepted contracts awaiting fulfillment");
+        return;
+    }
+
+    for contract in active {
+        println!("\n{} ({}, deadline {})", contract.id, contract.faction_symbol, contract.terms.deadline);
+        let deliveries = contract.terms.deliver.as_deref().unwrap_or_default();
+        if deliveries.is_empty() {
+            println!("  No delivery terms");
+        }
+        for delivery in deliveries {
+            let done = delivery.units_fulfilled >= delivery.units_required;
+            let percent = if delivery.units_required <= 0 {
+                100
+            } else {
+                i64::from(delivery.units_fulfilled.min(delivery.units_required)) * 100 / i64::from(delivery.units_required)
+            };
+            println!(
+                "  {} {} {} → {}: {}/{} units ({percent}%)",
+                if done { "✓" } else { " " },
+                progress_bar(delivery.units_fulfilled, delivery.units_required),
+                delivery.trade_symbol, delivery.destination_symbol, delivery.units_fulfilled, delivery.units_required
+            );
+        }
+    }
+}
+
+async fn accept_contract(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let Some(contract_id) = prompt_contract_id(ctx, false, false).await? else {
+        println!("No unaccepted contracts");
+        return Ok(());
+    };
+
+    match spacedust::apis::contracts_api::accept_contract(&ctx.configuration, &contract_id, 0).await {
+        Ok(res) => {
+            update_contract_row(ctx, &res.data.contract).await?;
+            tracing::info!(
+                contract_id = %res.data.contract.id, deadline = %res.data.contract.terms.deadline, credits = res.data.agent.credits,
+                "contract accepted"
+            );
+        }
+        Err(err_res) => {
+            println!("Error accepting contract {contract_id}: {}", describe_api_error(&err_res));
+        }
+    }
+    Ok(())
+}
+
+async fn fulfill_contract(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let Some(contract_id) = prompt_contract_id(ctx, true, false).await? else {
+        println!("No accepted contracts awaiting fulfillment");
+        return Ok(());
+    };
+
+    match spacedust::apis::contracts_api::fulfill_contract(&ctx.configuration, &contract_id, 0).await {
+        Ok(res) => {
+            update_contract_row(ctx, &res.data.contract).await?;
+            tracing::info!(contract_id = %res.data.contract.id, credits = res.data.agent.credits, "contract fulfilled");
+        }
+        Err(err_res) => {
+            println!("Error fulfilling contract {contract_id}: {}", describe_api_error(&err_res));
+        }
+    }
+    Ok(())
+}
+
+async fn list_ships(ctx: &Context) {
+    browse_paginated(st_util::paginate_ships(ctx), "ships", |ships| {
+        for ship in ships {
+            tracing::info!(?ship, "ships listed");
+        }
+    }).await;
+}
+
+/// Fraction of fuel capacity a ship must keep after a trip, unless overridden by `MIN_FUEL_RESERVE_PERCENT`.
+const DEFAULT_MIN_FUEL_RESERVE_PERCENT: f64 = 0.2;
+
+/// Checks that flying `ship` to `destination` leaves it above the fuel reserve.
+/// If it wouldn't, offers to refuel or switch to DRIFT, updating `ship` to match.
+/// Returns whether the trip may go ahead.
+async fn ensure_fuel_reserve(ctx: &Context, ship: &mut Ship, destination: &str) -> InquireResult<bool> {
+    if ship.fuel.capacity == 0 {
+        return Ok(true);
+    }
+    let reserve_percent = context::parse_env("MIN_FUEL_RESERVE_PERCENT", DEFAULT_MIN_FUEL_RESERVE_PERCENT);
+    let distance = match st_util::get_waypoint_distance(ctx, &ship.nav.waypoint_symbol, destination).await {
+        Ok(Some(distance)) => distance,
+        Ok(None) => {
+            println!("Could not estimate fuel use: {} or {destination} is missing from the database", ship.nav.waypoint_symbol);
+            return Ok(true);
+        }
+        Err(err) => {
+            println!("Could not estimate fuel use: {err}");
+            return Ok(true);
+        }
+    };
+    let reserve = f64::from(ship.fuel.capacity) * reserve_percent;
+
+    let mut options = vec!["Refuel first", "Switch to DRIFT mode"];
+    loop {
+        let cost = st_util::estimate_fuel_cost(distance, ship.nav.flight_mode);
+        let fuel_after_trip = ship.fuel.current - cost;
+        if f64::from(fuel_after_trip) > reserve {
+            return Ok(true);
+        }
+
+        println!(
+            "Warning: this trip costs about {cost} fuel in {} mode, leaving {fuel_after_trip}/{} (reserve is {reserve:.0})",
+            ship.nav.flight_mode.to_string(), ship.fuel.capacity
+        );
+        if options.is_empty() {
+            println!("Neither refueling nor drifting keeps {} above its fuel reserve, aborting", ship.symbol);
+            return Ok(false);
+        }
+        let mut choices = options.clone();
+        choices.push("Abort navigation");
+        let choice = Select::new("How do you want to proceed?", choices).prompt()?;
+        options.retain(|option| *option != choice);
+
+        match choice {
+            "Refuel first" => {
+                match st_util::ensure_ship_docked(ctx, ship).await {
+                    Ok(nav) => *ship.nav = nav,
+                    Err(err) => {
+                        println!("Error docking {}: {err}", ship.symbol);
+                        return Ok(false);
+                    }
+                }
+                match spacedust::apis::fleet_api::refuel_ship(&ctx.configuration, &ship.symbol, 0).await {
+                    Ok(res) => {
+                        ship.fuel = res.data.fuel;
+                        println!("Refueled {} to {}/{}", ship.symbol, ship.fuel.current, ship.fuel.capacity);
+                    }
+                    Err(err_res) => {
+                        println!("Error refueling {}: {}", ship.symbol, describe_api_error(&err_res));
+                    }
+                }
+            }
+            "Switch to DRIFT mode" => {
+                let mut request = PatchShipNavRequest::new();
+                request.flight_mode = Some(ShipNavFlightMode::Drift);
+                match spacedust::apis::fleet_api::patch_ship_nav(&ctx.configuration, &ship.symbol, Some(request)).await {
+                    Ok(res) => {
+                        ship.nav = res.data;
+                        println!("{} switched to DRIFT mode", ship.symbol);
+                    }
+                    Err(err_res) => {
+                        println!("Error changing flight mode of {}: {}", ship.symbol, describe_api_error(&err_res));
+                    }
+                }
+            }
+            _ => return Ok(false),
+        }
+    }
+}
+
+async fn ship_status(ctx: &Context) -> Result<(), sqlx::Error> {
+    let ships = match st_util::list_ships(ctx).await {
+        Ok(ships) => ships,
+        Err(err) => {
+            println!("Error listing ships: {}", describe_api_error(&err));
+            return Ok(());
+        }
+    };
+
+    let max_sell_distance = context::parse_env("MAX_SELL_DISTANCE", DEFAULT_MAX_SELL_DISTANCE);
+
+    println!("{:<20} {:<12} {:<16} {:<10} {:>9} {:>9} {:>10}", "SYMBOL", "ROLE", "WAYPOINT", "STATUS", "FUEL", "CARGO", "VALUE");
+    for ship in &ships {
+        let cargo_value = st_util::estimate_cargo_value(ctx, ship, max_sell_distance).await?;
+        println!(
+            "{:<20} {:<12} {:<16} {:<10} {:>9} {:>9} {:>10}",
+            ship.symbol,
+            ship.registration.role.to_string(),
+            ship.nav.waypoint_symbol,
+            ship.nav.status.to_string(),
+            format!("{}/{}", ship.fuel.current, ship.fuel.capacity),
+            format!("{}/{}", ship.cargo.units, ship.cargo.capacity),
+            cargo_value
+        );
+        if ship.nav.status == ShipNavStatus::InTransit {
+            println!("{:<20} arriving at {} at {}", "", ship.nav.route.destination.symbol, ship.nav.route.arrival);
+        }
+    }
+    println!("{} ships", ships.len());
+    Ok(())
+}
+
+/// Engine condition, out of 100, below which navigation asks for confirmation.
+const ENGINE_CONDITION_WARN: i32 = 50;
+
+/// Warns about a worn engine and asks whether to fly anyway. Returns whether the trip may go ahead.
+fn check_engine_condition(ship: &Ship) -> InquireResult<bool> {
+    let Some(condition) = ship.engine.condition.filter(|condition| *condition < ENGINE_CONDITION_WARN) else {
+        return Ok(true);
+    };
+    println!(
+        "Warning: {}'s engine is at {condition}% condition. It may fail in transit, and the trip may take longer than estimated. Consider repairing it before a long journey.",
+        ship.symbol
+    );
+    Confirm::new("Navigate anyway?")
+        .with_default(false)
+        .prompt()
+}
+
+/// Flies `ship` to a waypoint in its system, checking the fuel reserve and leaving orbit first if needed.
+/// Updates `ship` to match and returns whether it departed.
+async fn navigate_ship_to(ctx: &Context, ship: &mut Ship, waypoint_symbol: &str) -> Result<bool, AppError> {
+    Ok(check_navigation(ctx, ship, waypoint_symbol).await? && depart(ctx, ship, waypoint_symbol).await)
+}
+
+/// Checks that `ship` can fly to a waypoint, offering to refuel or drift if its fuel reserve would run low.
+async fn check_navigation(ctx: &Context, ship: &mut Ship, waypoint_symbol: &str) -> Result<bool, AppError> {
+    let system_symbol = match st_util::system_symbol_from_waypoint_symbol(waypoint_symbol) {
+        Ok(system_symbol) => system_symbol,
+        Err(err) => {
+            println!("Cannot navigate: {err}");
+            return Ok(false);
+        }
+    };
+
+    if system_symbol != ship.nav.system_symbol {
+        println!(
+            "Cannot navigate: {waypoint_symbol} is in ({}), but {} is in ({}). Navigation only works within a system.",
+            st_util::decode_system_symbol(&system_symbol), ship.symbol, st_util::decode_system_symbol(&ship.nav.system_symbol)
+        );
+        offer_interstellar_travel(ctx, ship, waypoint_symbol, &system_symbol).await?;
+        return Ok(false);
+    }
+
+    if ship.nav.status == ShipNavStatus::InTransit {
+        println!("{} is in transit, arriving at {}", ship.symbol, ship.nav.route.arrival);
+        return Ok(false);
+    }
+
+    if !check_engine_condition(ship)? {
+        return Ok(false);
+    }
+
+    Ok(ensure_fuel_reserve(ctx, ship, waypoint_symbol).await?)
+}
+
+/// Whether `ship` has a module whose API name starts with `prefix`.
+fn ship_has_module(ship: &Ship, prefix: &str) -> bool {
+    ship.modules.iter().any(|module| st_util::api_name(&module.symbol).starts_with(prefix))
+}
+
+/// Explains how `ship` could reach a waypoint in another system, and offers to jump or warp there if it can.
+async fn offer_interstellar_travel(ctx: &Context, ship: &mut Ship, waypoint_symbol: &str, system_symbol: &str) -> Result<(), AppError> {
+    let at_jump_gate = ctx.query_cache.waypoint(&ctx.db_pool, &ship.nav.waypoint_symbol)
+        .await?
+        .is_some_and(|waypoint| waypoint.waypoint_type == "JUMP_GATE");
+    let can_jump = at_jump_gate || ship_has_module(ship, "MODULE_JUMP_DRIVE");
+    let can_warp = ship_has_module(ship, "MODULE_WARP_DRIVE");
+
+    let jump = format!("Jump to {system_symbol}");
+    let warp = format!("Warp to {waypoint_symbol}");
+    let mut options = Vec::new();
+    if can_jump {
+        options.push(jump.clone());
+    }
+    if can_warp {
+        options.push(warp.clone());
+    }
+    if options.is_empty() {
+        println!(
+            "{} needs a jump gate or a warp drive to leave its system. Fly it to a jump gate in {} first.",
+            ship.symbol, ship.nav.system_symbol
+        );
+        return Ok(());
+    }
+    println!("Travel between systems needs a jump or a warp");
+    options.push("Cancel".to_string());
+    let choice = Select::new("How do you want to travel?", options).prompt()?;
+    if choice != jump && choice != warp {
+        return Ok(());
+    }
+
+    match st_util::ensure_ship_orbiting(ctx, ship).await {
+        Ok(nav) => *ship.nav = nav,
+        Err(err) => {
+            println!("Cannot leave with {}: {err}", ship.symbol);
+            return Ok(());
+        }
+    }
+    if choice == jump {
+        let request = JumpShipRequest::new(system_symbol.to_string());
+        match spacedust::apis::fleet_api::jump_ship(&ctx.configuration, &ship.symbol, Some(request)).await {
+            Ok(res) => {
+                if let Some(nav) = res.data.nav {
+                    ship.nav = nav;
+                }
+                println!(
+                    "{} jumped to {}, cooldown {}s. Navigate again to reach {waypoint_symbol}.",
+                    ship.symbol, system_symbol, res.data.cooldown.remaining_seconds
+                );
+            }
+            Err(err_res) => println!("Error jumping {}: {}", ship.symbol, describe_api_error(&err_res)),
+        }
+    } else {
+        let request = NavigateShipRequest::new(waypoint_symbol.to_string());
+        match spacedust::apis::fleet_api::warp_ship(&ctx.configuration, &ship.symbol, Some(request)).await {
+            Ok(res) => {
+                ship.nav = res.data.nav;
+                ship.fuel = res.data.fuel;
+                println!("{} is warping to {waypoint_symbol}, arriving at {}", ship.symbol, ship.nav.route.arrival);
+            }
+            Err(err_res) => println!("Error warping {}: {}", ship.symbol, describe_api_error(&err_res)),
+        }
+    }
+    Ok(())
+}
+
+/// Sends `ship` to a waypoint without any checks or prompts, leaving orbit first if needed. Updates `ship` to match.
+async fn depart(ctx: &Context, ship: &mut Ship, waypoint_symbol: &str) -> bool {
+    if ship.nav.status == ShipNavStatus::Docked {
+        println!("{} is docked, moving it into orbit first", ship.symbol);
+    }
+    match st_util::ensure_ship_orbiting(ctx, ship).await {
+        Ok(nav) => *ship.nav = nav,
+        Err(err) => {
+            println!("Cannot navigate {}: {err}", ship.symbol);
+            return false;
+        }
+    }
+
+    let request = NavigateShipRequest::new(waypoint_symbol.to_string());
+    match spacedust::apis::fleet_api::navigate_ship(&ctx.configuration, &ship.symbol, Some(request)).await {
+        Ok(res) => {
+            ship.nav = res.data.nav;
+            ship.fuel = res.data.fuel;
+            println!(
+                "{} is {} from {} to {}, arriving at {}",
+                ship.symbol,
+                ship.nav.status.to_string(),
+                ship.nav.route.departure.symbol,
+                ship.nav.route.destination.symbol,
+                ship.nav.route.arrival
+            );
+            println!("Fuel: {}/{}", ship.fuel.current, ship.fuel.capacity);
+            true
+        }
+        Err(err_res) => {
+            println!("Error navigating {}: {}", ship.symbol, describe_api_error(&err_res));
+            false
+        }
+    }
+}
+
+/// How long to wait before checking on a ship again when it is due but still reported in transit.
+const ARRIVAL_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Waits until `ship` finishes its current flight and returns its state on arrival.
+/// Returns `None` if fetching the ship fails.
+async fn wait_for_ship_arrival(ctx: &Context, ship: &Ship) -> Result<Option<Ship>, sqlx::Error> {
+    if ship.nav.status != ShipNavStatus::InTransit {
+        return Ok(Some(ship.clone()));
+    }
+    let mut arrival = ship.nav.route.arrival.clone();
+    loop {
+        let remaining: f64 = sqlx::query_scalar("SELECT GREATEST(EXTRACT(EPOCH FROM ($1::timestamptz - NOW())), 0)::float8")
+            .bind(&arrival)
+            .fetch_one(&ctx.db_pool)
+            .await?;
+        tokio::time::sleep(Duration::from_secs_f64(remaining).max(ARRIVAL_POLL_INTERVAL)).await;
+
+        let Some(current) = fetch_ship(ctx, &ship.symbol).await else {
+            return Ok(None);
+        };
+        if current.nav.status != ShipNavStatus::InTransit {
+            return Ok(Some(current));
+        }
+        arrival = current.nav.route.arrival;
+    }
+}
+
+async fn formation_navigate(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let ships = match st_util::list_ships(ctx).await {
+        Ok(ships) => ships,
+        Err(err) => {
+            println!("Error listing ships: {}", describe_api_error(&err));
+            return Ok(());
+        }
+    };
+    if ships.is_empty() {
+        println!("No ships");
+        return Ok(());
+    }
+
+    let options: Vec<String> = ships.iter().map(ship_option_label).collect();
+    let chosen: HashSet<usize> = MultiSelect::new("Select ships", options)
+        .raw_prompt()?
+        .into_iter()
+        .map(|option| option.index)
+        .collect();
+    if chosen.is_empty() {
+        println!("No ships selected");
+        return Ok(());
+    }
+    let Some(waypoint_symbol) = prompt_destination(ctx).await? else {
+        return Ok(());
+    };
+
+    // Checks can prompt, so they run one ship at a time before anyone departs.
+    let mut ready = Vec::new();
+    for (_, mut ship) in ships.into_iter().enumerate().filter(|(index, _)| chosen.contains(index)) {
+        if check_navigation(ctx, &mut ship, &waypoint_symbol).await? {
+            ready.push(ship);
+        }
+    }
+
+    let departures = join_all(ready.iter_mut().map(|ship| depart(ctx, ship, &waypoint_symbol))).await;
+    let departed: Vec<Ship> = ready.into_iter()
+        .zip(departures)
+        .filter_map(|(ship, departed)| departed.then_some(ship))
+        .collect();
+    if departed.is_empty() {
+        println!("No ships departed for {waypoint_symbol}");
+        return Ok(());
+    }
+    println!("{} of {} ships departed for {waypoint_symbol}, waiting for them to arrive", departed.len(), chosen.len());
+
+    let arrivals = join_all(departed.iter().map(|ship| async {
+        let arrived = wait_for_ship_arrival(ctx, ship).await?;
+        if let Some(arrived) = &arrived {
+            println!("{} arrived at {}", arrived.symbol, arrived.nav.waypoint_symbol);
+        }
+        Ok::<_, sqlx::Error>(arrived)
+    })).await;
+    let arrived = arrivals.into_iter().collect::<Result<Vec<_>, _>>()?.iter().flatten().count();
+    if arrived == departed.len() {
+        println!("All {arrived} ships have arrived at {waypoint_symbol}");
+    } else {
+        println!("{arrived} of {} ships confirmed arrived at {waypoint_symbol}", departed.len());
+    }
+    Ok(())
+}
+
+async fn navigate_ship(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let Some(mut ship) = prompt_ship(ctx).await? else {
+        return Ok(());
+    };
+    let Some(waypoint_symbol) = prompt_destination(ctx).await? else {
+        return Ok(());
+    };
+    navigate_ship_to(ctx, &mut ship, &waypoint_symbol).await?;
+    Ok(())
+}
+
+/// Whether there is a marketplace at a waypoint, fetching the waypoint if its traits aren't known yet.
+/// Returns `None` if the waypoint can't be fetched.
+async fn waypoint_is_marketplace(ctx: &Context, waypoint_symbol: &str) -> Result<Option<bool>, sqlx::Error> {
+    waypoint_has_trait(ctx, waypoint_symbol, |waypoint| waypoint.is_marketplace).await
+}
+
+/// Whether there is a shipyard at a waypoint, fetching the waypoint if its traits aren't known yet.
+/// Returns `None` if the waypoint can't be fetched.
+async fn waypoint_is_shipyard(ctx: &Context, waypoint_symbol: &str) -> Result<Option<bool>, sqlx::Error> {
+    waypoint_has_trait(ctx, waypoint_symbol, |waypoint| waypoint.is_shipyard).await
+}
+
+/// Whether a waypoint has a trait, using its `flag` from the waypoints table if set and fetching the waypoint otherwise.
+async fn waypoint_has_trait(ctx: &Context, waypoint_symbol: &str, flag: fn(&WaypointRow) -> Option<bool>) -> Result<Option<bool>, sqlx::Error> {
+    let known = ctx.query_cache.waypoint(&ctx.db_pool, waypoint_symbol)
+        .await?;
+    if let Some(flag) = known.as_ref().and_then(flag) {
+        return Ok(Some(flag));
+    }
+
+    let system_symbol = match st_util::system_symbol_from_waypoint_symbol(waypoint_symbol) {
+        Ok(system_symbol) => system_symbol,
+        Err(err) => {
+            println!("Error getting waypoint: {err}");
+            return Ok(None);
+        }
+    };
+    match st_util::get_waypoint_cached(ctx, &system_symbol, waypoint_symbol).await {
+        Ok(waypoint) => {
+            store_waypoint_traits(ctx, std::slice::from_ref(&waypoint)).await?;
+            Ok(flag(&WaypointRow::from(&waypoint)))
+        }
+        Err(err_res) => {
+            println!("Error getting waypoint {waypoint_symbol}: {}", describe_api_error(&err_res));
+            Ok(None)
+        }
+    }
+}
+
+async fn refuel_ship(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let Some(mut ship) = prompt_ship(ctx).await? else {
+        return Ok(());
+    };
+    if ship.nav.status == ShipNavStatus::InTransit {
+        println!("{} is in transit, arriving at {}", ship.symbol, ship.nav.route.arrival);
+        return Ok(());
+    }
+
+    let Some(is_marketplace) = waypoint_is_marketplace(ctx, &ship.nav.waypoint_symbol).await? else {
+        return Ok(());
+    };
+    if !is_marketplace {
+        let nearest = match st_util::find_nearest_waypoints_with_trait(ctx, &ship.nav.waypoint_symbol, "MARKETPLACE", 1).await {
+            Ok(nearest) => nearest,
+            Err(err) => {
+                tracing::error!(error = ?err, "finding marketplaces failed");
+                return Ok(());
+            }
+        };
+        let Some((marketplace, distance)) = nearest.into_iter().next() else {
+            println!("{} has no marketplace and none are known in {}", ship.nav.waypoint_symbol, ship.nav.system_symbol);
+            return Ok(());
+        };
+        let go = Confirm::new(&format!(
+            "{} has no marketplace. Navigate to {marketplace} ({distance:.1} away) first?",
+            ship.nav.waypoint_symbol
+        ))
+            .with_default(true)
+            .prompt()?;
+        if go && navigate_ship_to(ctx, &mut ship, &marketplace).await? {
+            println!("Refuel {} once it arrives", ship.symbol);
+        }
+        return Ok(());
+    }
+
+    let credits_before = match spacedust::apis::agents_api::get_my_agent(&ctx.configuration).await {
+        Ok(res) => res.data.credits,
+        Err(err_res) => {
+            println!("Error getting agent: {}", describe_api_error(&err_res));
+            return Ok(());
+        }
+    };
+
+    if let Err(err) = st_util::ensure_ship_docked(ctx, &ship).await {
+        println!("Error docking {}: {err}", ship.symbol);
+        return Ok(());
+    }
+
+    match spacedust::apis::fleet_api::refuel_ship(&ctx.configuration, &ship.symbol, 0).await {
+        Ok(res) => {
+            println!(
+                "Refueled {} to {}/{} for {} credits ({} left)",
+                ship.symbol,
+                res.data.fuel.current,
+                res.data.fuel.capacity,
+                credits_before - res.data.agent.credits,
+                res.data.agent.credits
+            );
+        }
+        Err(err_res) => {
+            println!("Error refueling {}: {}", ship.symbol, describe_api_error(&err_res));
+        }
+    }
+    Ok(())
+}
+
+/// Docks a ship that is in orbit so it can trade. Returns whether it is docked.
+async fn dock_for_trade(ctx: &Context, ship: &Ship) -> bool {
+    if ship.nav.status == ShipNavStatus::Docked {
+        return true;
+    }
+    println!("{} is not docked, docking it first", ship.symbol);
+    match st_util::ensure_ship_docked(ctx, ship).await {
+        Ok(_) => true,
+        Err(err) => {
+            println!("Error docking {}: {err}", ship.symbol);
+            false
+        }
+    }
+}
+
+/// Estimated depth of a trade good, for display next to its price.
+fn trade_good_depth(good: &MarketTradeGood) -> st_util::MarketDepth {
+    st_util::compute_market_depth(good.trade_volume.try_into().unwrap_or(0), good.supply, good.purchase_price.into())
+}
+
+/// Warns if an order is larger than the market is expected to absorb at the listed price.
+fn warn_market_depth(good: &MarketTradeGood, units: i32) {
+    let depth = trade_good_depth(good);
+    if i64::from(units) > i64::from(depth.units) {
+        println!("Warning: {units} units is more than the ~{} the market absorbs before the price moves", depth.units);
+    }
+}
+
+/// Prompts for a number of units between 1 and `max`.
+fn prompt_units(max: i32) -> InquireResult<i32> {
+    CustomType::<i32>::new(&format!("Units (1-{max})"))
+        .with_error_message("Enter a whole number")
+        .with_validator(move |units: &i32| -> Result<Validation, CustomUserError> {
+            Ok(if (1..=max).contains(units) {
+                Validation::Valid
+            } else {
+                Validation::Invalid(format!("Enter a number from 1 to {max}").into())
+            })
+        })
+        .prompt()
+}
+
+async fn buy_goods(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let Some(ship) = prompt_ship(ctx).await? else {
+        return Ok(());
+    };
+    let market = match st_util::get_market_for_ship(ctx, &ship).await {
+        Ok(market) => market,
+        Err(err) => {
+            println!("Cannot trade with {}: {err}", ship.symbol);
+            return Ok(());
+        }
+    };
+    record_market_prices(ctx, &market).await?;
+    let goods = market.trade_goods.unwrap_or_default();
+    if goods.is_empty() {
+        println!("{} has no trade goods listed", market.symbol);
+        return Ok(());
+    }
+    let free_space = ship.cargo.capacity - ship.cargo.units;
+    if free_space <= 0 {
+        println!("{} has no free cargo space", ship.symbol);
+        return Ok(());
+    }
+
+    let options: Vec<String> = goods.iter()
+        .map(|good| {
+            let depth = trade_good_depth(good);
+            format!(
+                "{:<24} buy {:>6}  Depth: ~{} units before price impact ({} credits)",
+                good.symbol, good.purchase_price, depth.units, depth.cost
+            )
+        })
+        .collect();
+    let choice = Select::new("Select good to buy", options).raw_prompt()?;
+    let good = &goods[choice.index];
+    let units = prompt_units(free_space)?;
+    warn_market_depth(good, units);
+
+    if !dock_for_trade(ctx, &ship).await {
+        return Ok(());
+    }
+    let request = PurchaseCargoRequest::new(good.symbol.clone(), units);
+    match spacedust::apis::fleet_api::purchase_cargo(&ctx.configuration, &ship.symbol, Some(request)).await {
+        Ok(res) => {
+            let data = &res.data;
+            println!(
+                "Bought {} {} at {} each: -{} credits ({} left)",
+                data.transaction.units, data.transaction.trade_symbol, data.transaction.price_per_unit,
+                data.transaction.total_price, data.agent.credits
+            );
+            println!("Cargo: {}/{}", data.cargo.units, data.cargo.capacity);
+        }
+        Err(err_res) => println!("Error buying {}: {}", good.symbol, describe_api_error(&err_res)),
+    }
+    Ok(())
+}
+
+/// Furthest a market may be for `SellGoods` to suggest it, or `ShipStatus` to value cargo at its prices,
+/// unless overridden by `MAX_SELL_DISTANCE`.
+const DEFAULT_MAX_SELL_DISTANCE: f64 = 500.0;
+
+/// Points out a nearby market that was seen paying more for a good than the local one.
+async fn suggest_better_sell_market(ctx: &Context, ship: &Ship, good: &MarketTradeGood) {
+    let max_distance = context::parse_env("MAX_SELL_DISTANCE", DEFAULT_MAX_SELL_DISTANCE);
+    match st_util::find_best_sell_market(ctx, ship, &good.symbol, max_distance).await {
+        Ok(Some(best)) if best.sell_price > good.sell_price && best.waypoint.symbol != ship.nav.waypoint_symbol => {
+            println!(
+                "Tip: {} last paid {} per unit for {} ({:.1} away), {} more than here",
+                best.waypoint.symbol, best.sell_price, good.symbol, best.distance, best.sell_price - good.sell_price
+            );
+        }
+        Ok(_) => {}
+        Err(err) => tracing::error!(error = ?err, "comparing markets failed"),
+    }
+}
+
+async fn sell_goods(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let Some(ship) = prompt_ship(ctx).await? else {
+        return Ok(());
+    };
+    let market = match st_util::get_market_for_ship(ctx, &ship).await {
+        Ok(market) => market,
+        Err(err) => {
+            println!("Cannot trade with {}: {err}", ship.symbol);
+            return Ok(());
+        }
+    };
+    record_market_prices(ctx, &market).await?;
+    let goods = market.trade_goods.unwrap_or_default();
+    let reserves = cargo_reserves(ctx).await?;
+    let reserved = |symbol: &str| reserves.get(symbol).copied().unwrap_or(0);
+    let sellable: Vec<_> = ship.cargo.inventory.iter()
+        .filter(|item| item.units > reserved(&item.symbol))
+        .filter_map(|item| goods.iter().find(|good| good.symbol == item.symbol).map(|good| (item, good)))
+        .collect();
+    if sellable.is_empty() {
+        println!("{} carries nothing that {} buys beyond its reserves", ship.symbol, market.symbol);
+        return Ok(());
+    }
+
+    let options: Vec<String> = sellable.iter()
+        .map(|(item, good)| format!(
+            "{:<24} sell {:>6}  (holding {}{})  Depth: ~{} units before price impact",
+            item.symbol, good.sell_price, item.units, reserve_note(reserved(&item.symbol)), trade_good_depth(good).units
+        ))
+        .collect();
+    let choice = Select::new("Select good to sell", options).raw_prompt()?;
+    let (item, good) = sellable[choice.index];
+    suggest_better_sell_market(ctx, &ship, good).await;
+    let units = prompt_units(item.units - reserved(&item.symbol))?;
+    warn_market_depth(good, units);
+
+    if !dock_for_trade(ctx, &ship).await {
+        return Ok(());
+    }
+    let request = SellCargoRequest::new(item.symbol.clone(), units);
+    match spacedust::apis::fleet_api::sell_cargo(&ctx.configuration, &ship.symbol, Some(request)).await {
+        Ok(res) => {
+            let data = &res.data;
+            println!(
+                "Sold {} {} at {} each: +{} credits ({} total)",
+                data.transaction.units, data.transaction.trade_symbol, data.transaction.price_per_unit,
+                data.transaction.total_price, data.agent.credits
+            );
+            println!("Cargo: {}/{}", data.cargo.units, data.cargo.capacity);
+        }
+        Err(err_res) => println!("Error selling {}: {}", item.symbol, describe_api_error(&err_res)),
+    }
+    Ok(())
+}
+
+/// Fetch the current state of a ship, printing the error if that fails.
+async fn fetch_ship(ctx: &Context, ship_symbol: &str) -> Option<Ship> {
+    match spacedust::apis::fleet_api::get_my_ship(&ctx.configuration, ship_symbol).await {
+        Ok(res) => Some(*res.data),
+        Err(err_res) => {
+            println!("Error getting ship {ship_symbol}: {}", describe_api_error(&err_res));
+            None
+        }
+    }
+}
+
+/// Print the cargo of two ships next to each other. Goods with a reserve are marked with `R`.
+fn print_cargo_side_by_side(
+    left_symbol: &str, left: &ShipCargo, right_symbol: &str, right: &ShipCargo, reserves: &HashMap<String, i32>
+) {
+    let header = |symbol: &str, cargo: &ShipCargo| format!("{symbol} ({}/{})", cargo.units, cargo.capacity);
+    let item = |cargo: &ShipCargo, index: usize| cargo.inventory.get(index)
+        .map(|item| format!(
+            "{:<24} {:>5} {}",
+            item.symbol, item.units, if reserves.contains_key(&item.symbol) { "R" } else { " " }
+        ))
+        .unwrap_or_default();
+
+    println!("{:<34} {}", header(left_symbol, left), header(right_symbol, right));
+    for index in 0..left.inventory.len().max(right.inventory.len()) {
+        println!("{:<34} {}", item(left, index), item(right, index));
+    }
+}
+
+/// Option in the transfer target list for typing a ship symbol instead.
+const OTHER_SHIP_OPTION: &str = "Other ship (enter symbol)";
+
+async fn transfer_cargo(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let ships = match st_util::list_ships(ctx).await {
+        Ok(ships) => ships,
+        Err(err) => {
+            println!("Error listing ships: {}", describe_api_error(&err));
+            return Ok(());
+        }
+    };
+    if ships.len() < 2 {
+        println!("Transferring cargo needs at least two ships");
+        return Ok(());
+    }
+
+    let options: Vec<String> = ships.iter().map(ship_option_label).collect();
+    let source_index = Select::new("Transfer from", options.clone()).raw_prompt()?.index;
+    let mut target_options: Vec<String> = options.into_iter().enumerate()
+        .filter(|(index, _)| *index != source_index)
+        .map(|(_, option)| option)
+        .collect();
+    target_options.push(OTHER_SHIP_OPTION.to_string());
+    let other_index = target_options.len() - 1;
+    let mut target_index = Select::new("Transfer to", target_options).raw_prompt()?.index;
+    let target_symbol = if target_index == other_index {
+        Text::new("Enter ship symbol")
+            .with_validator(symbol_validator(validate_ship_symbol))
+            .prompt()?
+    } else {
+        if target_index >= source_index {
+            target_index += 1;
+        }
+        ships[target_index].symbol.clone()
+    };
+
+    // Check positions against the ships' current state, they may have moved since they were listed.
+    let Some(source) = fetch_ship(ctx, &ships[source_index].symbol).await else {
+        return Ok(());
+    };
+    let Some(target) = fetch_ship(ctx, &target_symbol).await else {
+        return Ok(());
+    };
+    if target.symbol == source.symbol {
+        println!("Cannot transfer: {} is both the source and the target", source.symbol);
+        return Ok(());
+    }
+    if source.nav.waypoint_symbol != target.nav.waypoint_symbol {
+        println!(
+            "Cannot transfer: {} is at {} but {} is at {}",
+            source.symbol, source.nav.waypoint_symbol, target.symbol, target.nav.waypoint_symbol
+        );
+        return Ok(());
+    }
+    if let Some(ship) = [&source, &target].into_iter().find(|ship| ship.nav.status == ShipNavStatus::InTransit) {
+        println!("Cannot transfer: {} is in transit, arriving at {}", ship.symbol, ship.nav.route.arrival);
+        return Ok(());
+    }
+    if source.cargo.inventory.is_empty() {
+        println!("{} has no cargo", source.symbol);
+        return Ok(());
+    }
+    let free_capacity = target.cargo.capacity - target.cargo.units;
+    if free_capacity <= 0 {
+        println!("{} has no free cargo space", target.symbol);
+        return Ok(());
+    }
+
+    let reserves = cargo_reserves(ctx).await?;
+    let options: Vec<String> = source.cargo.inventory.iter()
+        .map(|item| format!(
+            "{:<24} {:>5} units{}",
+            item.symbol, item.units, reserve_note(reserves.get(&item.symbol).copied().unwrap_or(0))
+        ))
+        .collect();
+    let choice = Select::new("Select cargo to transfer", options).raw_prompt()?;
+    let item = &source.cargo.inventory[choice.index];
+    let units = prompt_units(item.units.min(free_capacity))?;
+
+    let request = TransferCargoRequest::new(item.symbol.clone(), units, target.symbol.clone());
+    match spacedust::apis::fleet_api::transfer_cargo(&ctx.configuration, &source.symbol, Some(request)).await {
+        Ok(res) => {
+            println!("Transferred {units} {} from {} to {}", item.symbol, source.symbol, target.symbol);
+            let target_cargo = fetch_ship(ctx, &target.symbol).await.map_or(target.cargo, |ship| ship.cargo);
+            print_cargo_side_by_side(&source.symbol, &res.data.cargo, &target.symbol, &target_cargo, &reserves);
+        }
+        Err(err_res) => println!("Error transferring {}: {}", item.symbol, describe_api_error(&err_res)),
+    }
+    Ok(())
+}
+
+/// Prompts for a known shipyard, or for a system to look for shipyards in.
+/// Returns `None` if no shipyard is found.
+async fn prompt_shipyard(ctx: &Context) -> Result<Option<String>, AppError> {
+    const OTHER_SYSTEM: &str = "Look for shipyards in another system";
+
+    let known: Vec<String> = sqlx::query_scalar("SELECT symbol FROM waypoints WHERE is_shipyard ORDER BY system_symbol, symbol")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    let mut options = known.clone();
+    options.push(OTHER_SYSTEM.to_string());
+    let choice = Select::new("Select shipyard", options).prompt()?;
+    if choice != OTHER_SYSTEM {
+        return Ok(Some(choice));
+    }
+
+    // Waypoints from the bulk systems listing have no traits yet, so check each of them.
+    let system_symbol = prompt_system_symbol(ctx).await?;
+    let unknown: Vec<String> = sqlx::query_scalar("SELECT symbol FROM waypoints WHERE system_symbol = $1 AND is_shipyard IS NULL")
+        .bind(&system_symbol)
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    for waypoint_symbol in &unknown {
+        waypoint_is_shipyard(ctx, waypoint_symbol).await?;
+    }
+
+    let shipyards: Vec<String> = sqlx::query_scalar("SELECT symbol FROM waypoints WHERE system_symbol = $1 AND is_shipyard ORDER BY symbol")
+        .bind(&system_symbol)
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    if shipyards.is_empty() {
+        println!("No shipyards found in {system_symbol}");
+        return Ok(None);
+    }
+    Ok(Some(Select::new("Select shipyard", shipyards).prompt()?))
+}
+
+async fn purchase_ship(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let Some(waypoint_symbol) = prompt_shipyard(ctx).await? else {
+        return Ok(());
+    };
+    let system_symbol = match st_util::system_symbol_from_waypoint_symbol(&waypoint_symbol) {
+        Ok(system_symbol) => system_symbol,
+        Err(err) => {
+            println!("{err}");
+            return Ok(());
+        }
+    };
+    let shipyard = match spacedust::apis::systems_api::get_shipyard(&ctx.configuration, &system_symbol, &waypoint_symbol).await {
+        Ok(res) => res.data,
+        Err(err_res) => {
+            println!("Error getting shipyard {waypoint_symbol}: {}", describe_api_error(&err_res));
+            return Ok(());
+        }
+    };
+
+    // Prices are only listed while one of our ships is at the shipyard.
+    let ship_types: Vec<ShipType> = shipyard.ship_types.iter().filter_map(|ship_type| ship_type.r#type).collect();
+    if ship_types.is_empty() {
+        println!("{waypoint_symbol} has no ships for sale");
+        return Ok(());
+    }
+    let listings = shipyard.ships.unwrap_or_default();
+    let options: Vec<String> = ship_types.iter()
+        .map(|ship_type| {
+            let price = listings.iter()
+                .find(|listing| listing.r#type == Some(*ship_type))
+                .map_or_else(|| "price unknown, no ship present".to_string(), |listing| format!("{} credits", listing.purchase_price));
+            format!("{:<24} {price}", ship_type.to_string())
+        })
+        .collect();
+    let choice = Select::new("Select ship type", options).raw_prompt()?;
+    let ship_type = ship_types[choice.index];
+
+    let request = PurchaseShipRequest::new(ship_type, waypoint_symbol.clone());
+    match spacedust::apis::fleet_api::purchase_ship(&ctx.configuration, Some(request)).await {
+        Ok(res) => {
+            let data = &res.data;
+            println!(
+                "Purchased {} ({}) for {} credits, {} credits left",
+                data.ship.symbol, data.ship.registration.role.to_string(), data.transaction.price, data.agent.credits
+            );
+        }
+        Err(err_res) => println!("Error purchasing {} at {waypoint_symbol}: {}", ship_type.to_string(), describe_api_error(&err_res)),
+    }
+    Ok(())
+}
+
+async fn toggle_orbit_dock(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let Some(ship) = prompt_ship(ctx).await? else {
+        return Ok(());
+    };
+    let result = match ship.nav.status {
+        ShipNavStatus::InTransit => {
+            println!("{} is in transit, arriving at {}", ship.symbol, ship.nav.route.arrival);
+            return Ok(());
+        }
+        ShipNavStatus::Docked => st_util::ensure_ship_orbiting(ctx, &ship).await,
+        ShipNavStatus::InOrbit => st_util::ensure_ship_docked(ctx, &ship).await,
+    };
+    match result {
+        Ok(nav) => println!("{} is now {} at {}", ship.symbol, nav.status.to_string(), nav.waypoint_symbol),
+        Err(err) => println!("Error changing nav status of {}: {err}", ship.symbol),
+    }
+    Ok(())
+}
+
+async fn update_market_prices(ctx: &Context) -> Result<(), AppError> {
+    let ships = match st_util::list_ships(ctx).await {
+        Ok(ships) => ships,
+        Err(err) => {
+            println!("Error listing ships: {}", describe_api_error(&err));
+            return Ok(());
+        }
+    };
+
+    let mut visited = HashSet::new();
+    let ships: Vec<&Ship> = ships.iter()
+        .filter(|ship| ship.nav.status != ShipNavStatus::InTransit)
+        .filter(|ship| visited.insert(ship.nav.waypoint_symbol.clone()))
+        .collect();
+    let results: Vec<_> = stream::iter(ships)
+        .map(|ship| async move { (ship, st_util::get_market_for_ship(ctx, ship).await) })
+        .buffer_unordered(api_concurrency())
+        .collect()
+        .await;
+
+    let (mut recorded, mut failed) = (0, 0);
+    for (ship, result) in results {
+        match result {
+            Ok(market) => {
+                record_market_prices(ctx, &market).await?;
+                println!("{}: recorded {} trade goods", market.symbol, market.trade_goods.map_or(0, |goods| goods.len()));
+                recorded += 1;
+            }
+            Err(st_util::ShipMarketError::NoMarket { .. }) => {}
+            Err(err) => {
+                println!("{}: {err}", ship.nav.waypoint_symbol);
+                failed += 1;
+            }
+        }
+    }
+    println!("Updated {recorded} markets, {failed} failed");
+    Ok(())
+}
+
+/// Default number of market requests in flight at once when refreshing several markets, unless overridden by `API_CONCURRENCY`.
+const DEFAULT_API_CONCURRENCY: usize = 4;
+
+fn api_concurrency() -> usize {
+    match context::parse_env("API_CONCURRENCY", DEFAULT_API_CONCURRENCY) {
+        0 => DEFAULT_API_CONCURRENCY,
+        concurrency => concurrency,
+    }
+}
+
+/// Fetches every known marketplace within `radius` of a waypoint concurrently, and records their prices.
+/// Returns how many markets were fetched and how many had prices to record, since prices are only listed where a ship is.
+///
+/// # Errors
+/// Propogates any database error
+async fn refresh_nearby_markets(ctx: &Context, center_symbol: &str, radius: f64) -> Result<(usize, usize), sqlx::Error> {
+    let marketplaces: Vec<WaypointRow> = st_util::get_economic_zone(ctx, center_symbol, radius).await?
+        .into_iter()
+        .filter(|waypoint| waypoint.is_marketplace == Some(true))
+        .collect();
+
+    let markets: Vec<Market> = stream::iter(&marketplaces)
+        .map(|waypoint| async move {
+            match spacedust::apis::systems_api::get_market(&ctx.configuration, &waypoint.system_symbol, &waypoint.symbol).await {
+                Ok(res) => Some(*res.data),
+                Err(err) => {
+                    println!("{}: {}", waypoint.symbol, describe_api_error(&err));
+                    None
+                }
+            }
+        })
+        .buffer_unordered(api_concurrency())
+        .filter_map(|market| async move { market })
+        .collect()
+        .await;
+
+    let mut priced = 0;
+    for market in &markets {
+        if market.trade_goods.as_ref().is_some_and(|goods| !goods.is_empty()) {
+            priced += 1;
+        }
+        ctx.api_cache.insert(format!("market:{}", market.symbol), market.clone());
+        record_market_prices(ctx, market).await?;
+    }
+    Ok((markets.len(), priced))
+}
+
+async fn refresh_nearby_markets_menu(ctx: &Context) -> Result<(), AppError> {
+    let center_symbol = prompt_waypoint_symbol()?;
+    let radius: f64 = CustomType::new("Enter radius").prompt()?;
+    match refresh_nearby_markets(ctx, &center_symbol, radius).await {
+        Ok((0, _)) => println!("No known marketplaces within {radius} of {center_symbol}"),
+        Ok((fetched, priced)) => println!("Fetched {fetched} markets, {priced} with prices to record"),
+        Err(err) => tracing::error!(error = ?err, "finding nearby marketplaces failed"),
+    }
+    Ok(())
+}
+
+async fn build_jump_gate_graph(ctx: &Context) -> Result<(), AppError> {
+    // Gates whose system already has outgoing connections were fetched on an earlier run.
+    let gates: Vec<(String, String)> = sqlx::query_as(
+        "SELECT w.symbol, w.system_symbol FROM waypoints w
+        WHERE w.type = 'JUMP_GATE'
+            AND NOT EXISTS (SELECT FROM jump_gate_connections c WHERE c.from_system = w.system_symbol)
+        ORDER BY w.symbol")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    if gates.is_empty() {
+        println!("Every known jump gate is already in the graph");
+        return Ok(());
+    }
+    let proceed = Confirm::new(&format!("Fetch {} jump gates? This makes one API call each.", gates.len()))
+        .with_default(true)
+        .prompt()?;
+    if !proceed {
+        return Ok(());
+    }
+
+    let (mut added, mut failed) = (0, 0);
+    for (i, (waypoint_symbol, system_symbol)) in gates.iter().enumerate() {
+        let jump_gate = match st_util::get_jump_gate_cached(ctx, system_symbol, waypoint_symbol).await {
+            Ok(jump_gate) => jump_gate,
+            Err(_) => {
+                failed += 1;
+                continue;
+            }
+        };
+        if jump_gate.connected_systems.is_empty() {
+            continue;
+        }
+
+        for connected_chunk in jump_gate.connected_systems.chunks(BIND_LIMIT / 3) {
+            let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+                "INSERT INTO jump_gate_connections(from_system, to_system, distance) "
+                );
+            query_builder.push_values(connected_chunk, |mut b, connected| {
+                b.push_bind(system_symbol)
+                    .push_bind(&connected.symbol)
+                    .push_bind(connected.distance);
+            });
+            query_builder.push(" ON CONFLICT DO NOTHING");
+            added += query_builder.build().execute(&ctx.db_pool).await?.rows_affected();
+        }
+
+        if (i + 1) % 50 == 0 {
+            println!("{}/{} gates fetched", i + 1, gates.len());
+        }
+    }
+    println!("Added {added} connections from {} gates ({failed} could not be fetched, e.g. uncharted)", gates.len());
+    Ok(())
+}
+
+async fn find_route(ctx: &Context) -> Result<(), AppError> {
+    println!("From:");
+    let from = prompt_system_symbol(ctx).await?;
+    println!("To:");
+    let to = prompt_system_symbol(ctx).await?;
+
+    let connections = match st_util::get_jump_gate_connections(ctx).await {
+        Ok(connections) => connections,
+        Err(err) => {
+            tracing::error!(error = ?err, "loading jump gate connections failed");
+            return Ok(());
+        }
+    };
+    if connections.is_empty() {
+        println!("No jump gate connections known yet. Run Build Jump Gate Graph first.");
+        return Ok(());
+    }
+
+    let cached: Option<(Option<f64>, i32)> = sqlx::query_as(
+        "SELECT euclidean_distance, jump_hops FROM system_distance_cache WHERE from_symbol = $1 AND to_symbol = $2")
+        .bind(&from)
+        .bind(&to)
+        .fetch_optional(&ctx.db_pool)
+        .await?;
+    if let Some((euclidean_distance, jump_hops)) = cached {
+        let straight_line = euclidean_distance.map_or_else(|| "unknown".to_string(), |distance| format!("{distance:.1}"));
+        println!("{jump_hops} jumps apart (fewest known), {straight_line} in a straight line");
+    }
+
+    let Some(route) = st_util::shortest_jump_route(&connections, &from, &to) else {
+        println!("No known route from {from} to {to}");
+        return Ok(());
+    };
+    let mut total = 0;
+    println!("{from}");
+    for hop in route.windows(2) {
+        let distance = connections.iter()
+            .find(|connection| connection.from_system == hop[0] && connection.to_system == hop[1])
+            .map_or(0, |connection| connection.distance);
+        total += distance;
+        println!("  └─ jump {distance} ──▶ {}", hop[1]);
+    }
+    println!("{} jumps, {total} total distance", route.len() - 1);
+    Ok(())
+}
+
+/// Most jumps between two systems for them to be stored in `system_distance_cache`.
+const DISTANCE_CACHE_MAX_HOPS: i32 = 10;
+
+/// Refills `system_distance_cache` with every pair of systems within `DISTANCE_CACHE_MAX_HOPS` jumps.
+async fn populate_distance_cache(ctx: &Context) -> Result<(), AppError> {
+    let connections = match st_util::get_jump_gate_connections(ctx).await {
+        Ok(connections) => connections,
+        Err(err) => {
+            tracing::error!(error = ?err, "loading jump gate connections failed");
+            return Ok(());
+        }
+    };
+    if connections.is_empty() {
+        println!("No jump gate connections known yet. Run Build Jump Gate Graph first.");
+        return Ok(());
+    }
+    let pairs = st_util::jump_hop_counts(&connections, DISTANCE_CACHE_MAX_HOPS);
+
+    let coordinates: HashMap<String, (i32, i32)> = sqlx::query_as::<_, (String, i32, i32)>("SELECT symbol, x, y FROM systems")
+        .fetch_all(&ctx.db_pool)
+        .await?
+        .into_iter()
+        .map(|(symbol, x, y)| (symbol, (x, y)))
+        .collect();
+    let euclidean_distance = |from: &str, to: &str| -> Option<f64> {
+        let (from_x, from_y) = coordinates.get(from)?;
+        let (to_x, to_y) = coordinates.get(to)?;
+        Some(f64::from(to_x - from_x).hypot(f64::from(to_y - from_y)))
+    };
+
+    let mut transaction = ctx.db_pool.begin().await?;
+    sqlx::query("DELETE FROM system_distance_cache").execute(&mut transaction).await?;
+    for chunk in pairs.chunks(BIND_LIMIT / 4) {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO system_distance_cache(from_symbol, to_symbol, euclidean_distance, jump_hops) "
+            );
+        query_builder.push_values(chunk, |mut b, (from, to, hops)| {
+            b.push_bind(from)
+                .push_bind(to)
+                .push_bind(euclidean_distance(from, to))
+                .push_bind(hops);
+        });
+        query_builder.build().execute(&mut transaction).await?;
+    }
+    transaction.commit().await?;
+    println!("Cached {} system pairs within {DISTANCE_CACHE_MAX_HOPS} jumps", pairs.len());
+    Ok(())
+}
+
+/// Prompts for an optional price threshold. Empty input means no threshold.
+fn prompt_price_threshold(message: &str) -> InquireResult<Option<i64>> {
+    CustomType::<i64>::new(message)
+        .with_help_message("Leave empty for no alert")
+        .prompt_skippable()
+}
+
+async fn manage_watchlist(ctx: &Context) -> Result<(), AppError> {
+    let watched: Vec<(String, Option<i64>, Option<i64>)> = sqlx::query_as(
+        "SELECT trade_symbol, min_sell_alert, max_buy_alert FROM trade_watchlist ORDER BY trade_symbol")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    let format_threshold = |threshold: Option<i64>| threshold.map_or_else(|| "-".to_string(), |threshold| threshold.to_string());
+    let mut options: Vec<String> = watched.iter()
+        .map(|(trade_symbol, min_sell, max_buy)| format!(
+            "Remove {trade_symbol:<24} (sell alert {}, buy alert {})",
+            format_threshold(*min_sell), format_threshold(*max_buy)
+        ))
+        .collect();
+    options.push("Add or update a good".to_string());
+    let choice = Select::new("Watchlist", options).raw_prompt()?;
+
+    if let Some((trade_symbol, ..)) = watched.get(choice.index) {
+        sqlx::query("DELETE FROM trade_watchlist WHERE trade_symbol = $1")
+            .bind(trade_symbol)
+            .execute(&ctx.db_pool)
+            .await?;
+        println!("Stopped watching {trade_symbol}");
+        return Ok(());
+    }
+
+    let trade_symbol = Text::new("Trade symbol").prompt()?.trim().to_uppercase();
+    let min_sell = prompt_price_threshold("Alert when a market buys it for at least")?;
+    let max_buy = prompt_price_threshold("Alert when a market sells it for at most")?;
+    sqlx::query("INSERT INTO trade_watchlist(trade_symbol, min_sell_alert, max_buy_alert) VALUES ($1, $2, $3)
+                ON CONFLICT (trade_symbol) DO UPDATE SET min_sell_alert = EXCLUDED.min_sell_alert, max_buy_alert = EXCLUDED.max_buy_alert")
+        .bind(&trade_symbol)
+        .bind(min_sell)
+        .bind(max_buy)
+        .execute(&ctx.db_pool)
+        .await?;
+    println!("Watching {trade_symbol}");
+    Ok(())
+}
+
+async fn watchlist_prices(ctx: &Context) -> Result<(), AppError> {
+    let prices: Vec<(String, String, i32, i32, String, String)> = sqlx::query_as(
+        "SELECT m.trade_symbol, m.waypoint_symbol, m.purchase_price, m.sell_price, m.supply,
+            to_char(m.observed_at, 'YYYY-MM-DD HH24:MI')
+        FROM market_prices m JOIN trade_watchlist w ON w.trade_symbol = m.trade_symbol
+        ORDER BY m.trade_symbol, m.sell_price DESC")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    if prices.is_empty() {
+        println!("No recorded prices for watched goods");
+        return Ok(());
+    }
+    let mut current_good = String::new();
+    for (trade_symbol, waypoint_symbol, purchase_price, sell_price, supply, observed_at) in prices {
+        if trade_symbol != current_good {
+            println!("\n{trade_symbol}");
+            println!("  {:<16} {:>6} {:>6} {:<10} OBSERVED", "MARKET", "BUY", "SELL", "SUPPLY");
+            current_good = trade_symbol;
+        }
+        println!("  {waypoint_symbol:<16} {purchase_price:>6} {sell_price:>6} {supply:<10} {observed_at}");
+    }
+    Ok(())
+}
+
+async fn list_waypoint_markets(ctx: &Context) -> Result<(), AppError> {
+    let system_symbol = prompt_system_symbol(ctx).await?;
+
+    // Markets without any recorded prices are fetched live. Prices are only included when a ship is present.
+    let unrecorded: Vec<String> = sqlx::query_scalar(
+        "SELECT w.symbol FROM waypoints w
+        WHERE w.system_symbol = $1 AND w.is_marketplace
+            AND NOT EXISTS (SELECT FROM market_prices m WHERE m.waypoint_symbol = w.symbol)
+        ORDER BY w.symbol")
+        .bind(&system_symbol)
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    let mut without_prices = Vec::new();
+    for waypoint_symbol in unrecorded {
+        match st_util::get_market_cached(ctx, &system_symbol, &waypoint_symbol).await {
+            Ok(market) if market.trade_goods.as_ref().is_some_and(|goods| !goods.is_empty()) => record_market_prices(ctx, &market).await?,
+            Ok(_) => without_prices.push(waypoint_symbol),
+            Err(err_res) => println!("Error getting market {waypoint_symbol}: {}", describe_api_error(&err_res)),
+        }
+    }
+
+    let prices: Vec<(String, String, i32, i32, String, i64)> = sqlx::query_as(
+        "SELECT m.waypoint_symbol, m.trade_symbol, m.purchase_price, m.sell_price, m.supply,
+            (EXTRACT(EPOCH FROM NOW() - m.observed_at) / 60)::bigint
+        FROM market_prices m JOIN waypoints w ON w.symbol = m.waypoint_symbol
+        WHERE w.system_symbol = $1
+        ORDER BY m.waypoint_symbol, m.trade_symbol")
+        .bind(&system_symbol)
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    let mut current_waypoint = String::new();
+    for (waypoint_symbol, trade_symbol, purchase_price, sell_price, supply, minutes_ago) in &prices {
+        if *waypoint_symbol != current_waypoint {
+            println!("\n{waypoint_symbol}");
+            println!("  {:<24} {:>6} {:>6} {:<10} OBSERVED", "GOOD", "BUY", "SELL", "SUPPLY");
+            current_waypoint = waypoint_symbol.clone();
+        }
+        println!("  {trade_symbol:<24} {purchase_price:>6} {sell_price:>6} {supply:<10} {minutes_ago} min ago");
+    }
+    for waypoint_symbol in &without_prices {
+        println!("\n{waypoint_symbol}\n  No prices known, a ship must visit to see them");
+    }
+    if prices.is_empty() && without_prices.is_empty() {
+        println!("No known marketplaces in {system_symbol}");
+    }
+    Ok(())
+}
+
+/// The number of units of each good that must be kept rather than sold.
+async fn cargo_reserves(ctx: &Context) -> Result<HashMap<String, i32>, sqlx::Error> {
+    Ok(sqlx::query_as("SELECT trade_symbol, min_units_reserved FROM cargo_reserves")
+        .fetch_all(&ctx.db_pool)
+        .await?
+        .into_iter()
+        .collect())
+}
+
+/// Note to append to a cargo line for a good with `reserved` units kept back, if any.
+fn reserve_note(reserved: i32) -> String {
+    if reserved > 0 {
+        format!(", {reserved} reserved")
+    } else {
+        String::new()
+    }
+}
+
+async fn manage_reserves(ctx: &Context) -> Result<(), AppError> {
+    let reserves: Vec<(String, i32)> = sqlx::query_as(
+        "SELECT trade_symbol, min_units_reserved FROM cargo_reserves ORDER BY trade_symbol")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    let mut options: Vec<String> = reserves.iter()
+        .map(|(trade_symbol, units)| format!("Remove {trade_symbol:<24} (keep {units} units)"))
+        .collect();
+    options.push("Add or update a reserve".to_string());
+    let choice = Select::new("Cargo reserves", options).raw_prompt()?;
+
+    if let Some((trade_symbol, _)) = reserves.get(choice.index) {
+        sqlx::query("DELETE FROM cargo_reserves WHERE trade_symbol = $1")
+            .bind(trade_symbol)
+            .execute(&ctx.db_pool)
+            .await?;
+        println!("{trade_symbol} is no longer reserved");
+        return Ok(());
+    }
+
+    let trade_symbol = Text::new("Trade symbol").prompt()?.trim().to_uppercase();
+    let units: i32 = CustomType::new("Units to keep").prompt()?;
+    sqlx::query("INSERT INTO cargo_reserves(trade_symbol, min_units_reserved) VALUES ($1, $2)
+                ON CONFLICT (trade_symbol) DO UPDATE SET min_units_reserved = EXCLUDED.min_units_reserved")
+        .bind(&trade_symbol)
+        .bind(units.max(0))
+        .execute(&ctx.db_pool)
+        .await?;
+    println!("Keeping at least {} units of {trade_symbol} when selling", units.max(0));
+    Ok(())
+}
+
+/// Number of opportunities listed by `FindBestTrade`.
+const BEST_TRADE_LIMIT: i64 = 10;
+
+async fn find_best_trade(ctx: &Context) {
+    let trades = match st_util::find_best_trades(ctx, BEST_TRADE_LIMIT).await {
+        Ok(trades) => trades,
+        Err(err) => {
+            tracing::error!(error = ?err, "finding trades failed");
+            return;
+        }
+    };
+
+    if trades.is_empty() {
+        println!("No profitable trades in recorded market prices. Visit more markets or run Update Market Prices.");
+        return;
+    }
+    println!("{:<24} {:<16} {:<16} {:>6} {:>6} {:>6} {:>8}", "GOOD", "BUY AT", "SELL AT", "BUY", "SELL", "MARGIN", "DISTANCE");
+    for trade in trades {
+        let distance = trade.distance.map_or_else(|| "?".to_string(), |distance| format!("{distance:.1}"));
+        println!(
+            "{:<24} {:<16} {:<16} {:>6} {:>6} {:>6} {distance:>8}",
+            trade.trade_symbol, trade.buy_waypoint, trade.sell_waypoint, trade.purchase_price, trade.sell_price, trade.margin
+        );
+    }
+}
+
+async fn survey_waypoint(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let Some(ship) = prompt_ship(ctx).await? else {
+        return Ok(());
+    };
+    if !orbit_for_mining(ctx, &ship).await {
+        return Ok(());
+    }
+    wait_for_cooldown(ctx, &ship.symbol).await?;
+
+    match spacedust::apis::fleet_api::create_survey(&ctx.configuration, &ship.symbol, 0).await {
+        Ok(res) => {
+            let surveys = &res.data.surveys;
+            if let Err(err) = st_util::store_surveys(ctx, surveys).await {
+                tracing::error!(error = ?err, "storing surveys failed");
+            }
+            for survey in surveys {
+                let deposits: Vec<&str> = survey.deposits.iter().map(|deposit| deposit.symbol.as_str()).collect();
+                println!(
+                    "{} ({}, expires {}): {}",
+                    survey.signature, st_util::api_name(&survey.size), survey.expiration, deposits.join(", ")
+                );
+            }
+            println!("Cooldown: {}s", res.data.cooldown.remaining_seconds);
+            record_cooldown(ctx, &res.data.cooldown).await?;
+        }
+        Err(err_res) => println!("Error surveying with {}: {}", ship.symbol, describe_api_error(&err_res)),
+    }
+    Ok(())
+}
+
+async fn extract_resources(ctx: &Context) -> Result<(), AppError> {
+    invalidate_status_bar(ctx);
+    let Some(ship) = prompt_ship(ctx).await? else {
+        return Ok(());
+    };
+    if !orbit_for_mining(ctx, &ship).await {
+        return Ok(());
+    }
+    wait_for_cooldown(ctx, &ship.symbol).await?;
+
+    let mut surveys = match st_util::get_active_surveys(ctx, &ship.nav.waypoint_symbol).await {
+        Ok(surveys) => surveys,
+        Err(err) => {
+            tracing::error!(error = ?err, "getting surveys failed");
+            Vec::new()
+        }
+    };
+    let mut request = ExtractResourcesRequest::new();
+    if !surveys.is_empty() {
+        let mut options: Vec<String> = surveys.iter()
+            .map(|survey| {
+                let deposits: Vec<&str> = survey.deposits.iter().map(|deposit| deposit.symbol.as_str()).collect();
+                format!("{} ({}): {}", survey.signature, st_util::api_name(&survey.size), deposits.join(", "))
+            })
+            .collect();
+        options.push("No survey".to_string());
+        let choice = Select::new("Select survey", options).raw_prompt()?;
+        if choice.index < surveys.len() {
+            request.survey = Some(Box::new(surveys.swap_remove(choice.index)));
+        }
+    }
+
+    match spacedust::apis::fleet_api::extract_resources(&ctx.configuration, &ship.symbol, Some(request)).await {
+        Ok(res) => {
+            let extracted = &res.data.extraction.r#yield;
+            println!("Extracted {} {}", extracted.units, extracted.symbol);
+            println!("Cargo: {}/{}", res.data.cargo.units, res.data.cargo.capacity);
+            println!("Cooldown: {}s", res.data.cooldown.remaining_seconds);
+            record_cooldown(ctx, &res.data.cooldown).await?;
+        }
+        Err(err_res) => println!("Error extracting with {}: {}", ship.symbol, describe_api_error(&err_res)),
+    }
+    Ok(())
+}
+
+/// Stores when a ship's cooldown ends, so later operations can wait for it.
+async fn record_cooldown(ctx: &Context, cooldown: &Cooldown) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO ship_cooldowns(ship_symbol, cooldown_expiry) VALUES ($1, $2::timestamptz)
+                ON CONFLICT (ship_symbol) DO UPDATE SET cooldown_expiry = EXCLUDED.cooldown_expiry")
+        .bind(&cooldown.ship_symbol)
+        .bind(&cooldown.expiration)
+        .execute(&ctx.db_pool)
+        .await?;
+    Ok(())
+}
+
+/// Waits until the stored cooldown of a ship has expired, counting down the seconds left.
+async fn wait_for_cooldown(ctx: &Context, ship_symbol: &str) -> Result<(), AppError> {
+    let remaining: Option<f64> = sqlx::query_scalar(
+        "SELECT EXTRACT(EPOCH FROM (cooldown_expiry - NOW()))::float8 FROM ship_cooldowns WHERE ship_symbol = $1")
+        .bind(ship_symbol)
+        .fetch_optional(&ctx.db_pool)
+        .await?;
+    let Some(remaining) = remaining.filter(|remaining| *remaining > 0.0) else {
+        return Ok(());
+    };
+
+    let expiry = tokio::time::Instant::now() + Duration::from_secs_f64(remaining);
+    loop {
+        let left = expiry.saturating_duration_since(tokio::time::Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        print!("\r{ship_symbol} is on cooldown: {}s remaining ", left.as_secs_f64().ceil());
+        io::stdout().flush()?;
+        tokio::time::sleep(left.min(Duration::from_secs(1))).await;
+    }
+    println!("\r{ship_symbol} is ready{}", " ".repeat(24));
+    Ok(())
+}
+
+/// Surveying and extracting happen from orbit. Moves a docked ship into orbit and returns whether it is there.
+async fn orbit_for_mining(ctx: &Context, ship: &Ship) -> bool {
+    if ship.nav.status == ShipNavStatus::Docked {
+        println!("{} is docked, moving it into orbit first", ship.symbol);
+    }
+    match st_util::ensure_ship_orbiting(ctx, ship).await {
+        Ok(_) => true,
+        Err(err) => {
+            println!("Cannot mine with {}: {err}", ship.symbol);
+            false
+        }
+    }
+}
+
+fn log_waypoint(waypoint: &WaypointRow) {
+    tracing::info!(
+        symbol = %waypoint.symbol, waypoint_type = %waypoint.waypoint_type, x = waypoint.x, y = waypoint.y,
+        traits = %waypoint.traits.join(","), faction = ?waypoint.faction, orbitals = %waypoint.orbitals.join(","),
+        "waypoint fetched"
+    );
+}
+
+//TODO: have this populate more of the database with whatever useful information
+async fn list_waypoints(ctx: &Context) -> Result<(), AppError> {
+    let system_symbol = &prompt_system_symbol(ctx).await?;
+
+    match st_util::list_system_waypoints(ctx, system_symbol).await {
+        Ok(waypoints) => {
+            store_waypoint_traits(ctx, &waypoints).await?;
+            for waypoint in waypoints.iter().map(WaypointRow::from) {
+                println!(
+                    "{} {:<20} {:<16} ({}, {}) {}",
+                    waypoint_type_name_icon(&waypoint.waypoint_type),
+                    waypoint.symbol,
+                    waypoint.waypoint_type,
+                    waypoint.x,
+                    waypoint.y,
+                    marketplace_icon(waypoint.is_marketplace)
+                );
+                log_waypoint(&waypoint);
+            }
+        }
+        Err(err) => tracing::error!(error = %describe_api_error(&err), %system_symbol, "listing waypoints failed")
+    }
+
+    Ok(())
+}
+
+async fn get_waypoint(ctx: &Context) -> Result<(), AppError> {
+    let waypoint_symbol = prompt_waypoint_symbol()?;
+    let system_symbol = match st_util::system_symbol_from_waypoint_symbol(&waypoint_symbol) {
+        Ok(system_symbol) => system_symbol,
+        Err(err) => {
+            println!("{err}");
+            return Ok(());
+        }
+    };
+
+    match st_util::get_waypoint_cached(ctx, &system_symbol, &waypoint_symbol).await {
+        Ok(waypoint) => {
+            store_waypoint_traits(ctx, std::slice::from_ref(&waypoint)).await?;
+            let parts = st_util::decode_waypoint_symbol(&waypoint_symbol);
+            println!("{} Waypoint {} ({parts})", waypoint_type_icon(waypoint.r#type), parts.waypoint_id);
+            let row = WaypointRow::from(&waypoint);
+            log_waypoint(&row);
+
+            let waypoint_type = row.waypoint_type;
+            let tip: Option<String> = sqlx::query_scalar("SELECT tip FROM waypoint_type_tips WHERE waypoint_type = $1")
+                .bind(&waypoint_type)
+                .fetch_optional(&ctx.db_pool)
+                .await?;
+            if let Some(tip) = tip {
+                println!("Tip ({waypoint_type}): {tip}");
+            }
+        }
+        Err(err_res) => {
+            tracing::error!(error = %describe_api_error(&err_res), %waypoint_symbol, "waypoint fetch failed");
+        }
+    }
+    Ok(())
+}
+
+/// Number of marketplaces listed by `FindNearestMarketplace`.
+const NEAREST_MARKETPLACE_LIMIT: u32 = 10;
+
+async fn find_nearest_marketplace(ctx: &Context) -> Result<(), AppError> {
+    let origin_symbol = prompt_waypoint_symbol()?;
+
+    match st_util::find_nearest_waypoints_with_trait(ctx, &origin_symbol, "MARKETPLACE", NEAREST_MARKETPLACE_LIMIT).await {
+        Ok(marketplaces) if marketplaces.is_empty() => {
+            println!("No known marketplaces near {origin_symbol}. List the system's waypoints to record their traits.");
+        }
+        Ok(marketplaces) => {
+            println!("{:>4} {:<20} {:>8}", "RANK", "SYMBOL", "DISTANCE");
+            for (rank, (symbol, distance)) in marketplaces.iter().enumerate() {
+                println!("{:>4} {symbol:<20} {distance:>8.1}", rank + 1);
+            }
+        }
+        Err(sqlx::Error::RowNotFound) => println!("{origin_symbol} is not in the waypoints table"),
+        Err(err) => tracing::error!(error = ?err, "finding marketplaces failed"),
+    }
+    Ok(())
+}
+
+async fn search_waypoints_by_trait(ctx: &Context) -> Result<(), AppError> {
+    let trait_symbols: Vec<String> = sqlx::query_scalar("SELECT DISTINCT trait_symbol FROM waypoint_traits ORDER BY trait_symbol")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    if trait_symbols.is_empty() {
+        println!("No waypoint traits recorded yet. List a system's waypoints to record them.");
+        return Ok(());
+    }
+
+    let trait_symbol = Select::new("Select trait", trait_symbols).prompt()?;
+
+    let matches: Vec<(String, String, Option<String>, Option<bool>)> = sqlx::query_as(
+        "SELECT t.waypoint_symbol, COALESCE(w.system_symbol, '?'), w.type, w.is_marketplace FROM waypoint_traits t
+        LEFT JOIN waypoints w ON w.symbol = t.waypoint_symbol
+        WHERE t.trait_symbol = $1 ORDER BY 2, 1")
+        .bind(&trait_symbol)
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    println!("{} waypoint(s) with {trait_symbol}:", matches.len());
+    println!("   {:<20} SYSTEM", "WAYPOINT");
+    for (waypoint_symbol, system_symbol, waypoint_type, is_marketplace) in matches {
+        let icon = waypoint_type.as_deref().map_or('?', waypoint_type_name_icon);
+        println!("{icon} {waypoint_symbol:<20} {system_symbol:<10} {}", marketplace_icon(is_marketplace));
+    }
+    Ok(())
+}
+
+/// A referential integrity check between two tables.
+struct IntegrityCheck {
+    description: &'static str,
+    /// Selects the symbols of all orphaned rows.
+    find_query: &'static str,
+    /// Deletes all orphaned rows.
+    clean_query: &'static str,
+}
+
+const INTEGRITY_CHECKS: &[IntegrityCheck] = &[
+    IntegrityCheck {
+        description: "waypoints.system_symbol not in systems.symbol",
+        find_query: "SELECT symbol FROM waypoints w WHERE NOT EXISTS (SELECT FROM systems s WHERE s.symbol = w.system_symbol)",
+        clean_query: "DELETE FROM waypoints w WHERE NOT EXISTS (SELECT FROM systems s WHERE s.symbol = w.system_symbol)",
+    },
+    IntegrityCheck {
+        description: "market_prices.waypoint_symbol not in waypoints.symbol",
+        find_query: "SELECT DISTINCT waypoint_symbol FROM market_prices m WHERE NOT EXISTS (SELECT FROM waypoints w WHERE w.symbol = m.waypoint_symbol)",
+        clean_query: "DELETE FROM market_prices m WHERE NOT EXISTS (SELECT FROM waypoints w WHERE w.symbol = m.waypoint_symbol)",
+    },
+];
+
+async fn check_data_integrity(ctx: &Context) -> Result<(), AppError> {
+    let mut violated_checks = Vec::new();
+
+    for check in INTEGRITY_CHECKS {
+        let orphans: Vec<(String,)> = sqlx::query_as(check.find_query)
+            .fetch_all(&ctx.db_pool)
+            .await?;
+        if orphans.is_empty() {
+            println!("OK: {}", check.description);
+        } else {
+            println!("WARNING: {} orphaned rows ({})", orphans.len(), check.description);
+            for (symbol,) in &orphans {
+                println!("    {symbol}");
+            }
+            violated_checks.push(check);
+        }
+    }
+
+    if violated_checks.is_empty() {
+        return Ok(());
+    }
+
+    match Confirm::new("Delete orphaned records?").with_default(false).prompt() {
+        Ok(true) => {
+            for check in violated_checks {
+                let deleted = sqlx::query(check.clean_query)
+                    .execute(&ctx.db_pool)
+                    .await?
+                    .rows_affected();
+                println!("Deleted {deleted} rows ({})", check.description);
+            }
+            ctx.query_cache.invalidate_waypoints();
+            ctx.query_cache.invalidate_systems();
+        }
+        Ok(false) => {}
+        Err(err) => tracing::error!(error = %err, "prompt failed"),
+    }
+    Ok(())
+}
+
+async fn bookmark_system(ctx: &Context) -> Result<(), AppError> {
+    let system_symbol = Text::new("Enter system symbol")
+        .with_validator(symbol_validator(validate_system_symbol))
+        .prompt()?;
+    let label = Text::new("Enter label (optional)").prompt()?;
+    let label = if label.is_empty() { None } else { Some(label) };
+
+    let system = ctx.query_cache.system(&ctx.db_pool, &system_symbol)
+        .await?;
+    match system {
+        Some(system) => println!(
+            "{system_symbol}: {} at ({}, {}), {}",
+            system.system_type,
+            system.x,
+            system.y,
+            system.controlling_faction.as_deref().unwrap_or("unclaimed")
+        ),
+        None => println!("Warning: {system_symbol} is not in the systems table"),
+    }
+
+    sqlx::query("INSERT INTO system_bookmarks(system_symbol, label) VALUES ($1, $2)
+                ON CONFLICT (system_symbol) DO UPDATE SET label = EXCLUDED.label")
+        .bind(&system_symbol)
+        .bind(label)
+        .execute(&ctx.db_pool)
+        .await?;
+    println!("Bookmarked {system_symbol}");
+    Ok(())
+}
+
+async fn list_bookmarks(ctx: &Context) -> Result<(), AppError> {
+    let bookmarks: Vec<(String, Option<String>, String, Option<String>)> = sqlx::query_as(
+        "SELECT b.system_symbol, b.label, to_char(b.created_at, 'YYYY-MM-DD HH24:MI'), n.nickname FROM system_bookmarks b
+        LEFT JOIN system_nicknames n ON n.system_symbol = b.system_symbol
+        ORDER BY b.created_at"
+        )
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    if bookmarks.is_empty() {
+        println!("No bookmarked systems");
+        return Ok(());
+    }
+    for (symbol, label, created_at, nickname) in bookmarks {
+        println!("{symbol:<12} {:<16} {:<24} {created_at}", nickname.unwrap_or_default(), label.unwrap_or_default());
+    }
+    Ok(())
+}
+
+async fn nickname_system(ctx: &Context) -> Result<(), AppError> {
+    let system_symbol = Text::new("Enter system symbol")
+        .with_validator(symbol_validator(validate_system_symbol))
+        .prompt()?;
+    let nickname = Text::new("Enter nickname (empty to remove)")
+        .with_validator(|input: &str| -> Result<Validation, CustomUserError> {
+            // A nickname shaped like a symbol would shadow the real system with that symbol.
+            Ok(if validate_system_symbol(input).is_ok() {
+                Validation::Invalid("Nicknames can't look like system symbols".into())
+            } else {
+                Validation::Valid
+            })
+        })
+        .prompt()?;
+    let nickname = nickname.trim();
+
+    if nickname.is_empty() {
+        sqlx::query("DELETE FROM system_nicknames WHERE system_symbol = $1")
+            .bind(&system_symbol)
+            .execute(&ctx.db_pool)
+            .await?;
+        println!("Removed nickname of {system_symbol}");
+        return Ok(());
+    }
+
+    let taken: Option<String> = sqlx::query_scalar("SELECT system_symbol FROM system_nicknames WHERE lower(nickname) = lower($1) AND system_symbol <> $2")
+        .bind(nickname)
+        .bind(&system_symbol)
+        .fetch_optional(&ctx.db_pool)
+        .await?;
+    if let Some(other) = taken {
+        println!("{nickname} is already the nickname of {other}");
+        return Ok(());
+    }
+
+    sqlx::query("INSERT INTO system_nicknames(system_symbol, nickname) VALUES ($1, $2)
+                ON CONFLICT (system_symbol) DO UPDATE SET nickname = EXCLUDED.nickname")
+        .bind(&system_symbol)
+        .bind(nickname)
+        .execute(&ctx.db_pool)
+        .await?;
+    println!("{system_symbol} is now known as {nickname}");
+    Ok(())
+}
+
+/// Prints a grid with one cell per sector, showing the initial of the faction controlling the most systems in it.
+async fn faction_map(ctx: &Context) -> Result<(), AppError> {
+    let rows: Vec<(String, Option<String>, i64)> = sqlx::query_as(
+        "SELECT sector_symbol, controlling_faction, COUNT(*) FROM systems GROUP BY sector_symbol, controlling_faction ORDER BY sector_symbol"
+        )
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    // (sector, dominant faction, systems controlled by it, total systems)
+    let mut sectors: Vec<(String, Option<String>, i64, i64)> = Vec::new();
+    for (sector, faction, count) in rows {
+        if sectors.last().is_none_or(|(last, ..)| *last != sector) {
+            sectors.push((sector, None, 0, 0));
+        }
+        let Some(entry) = sectors.last_mut() else { continue };
+        entry.3 += count;
+        if faction.is_some() && count > entry.2 {
+            entry.1 = faction;
+            entry.2 = count;
+        }
+    }
+
+    if sectors.is_empty() {
+        println!("No systems in the database");
+        return Ok(());
+    }
+
+    let columns = (sectors.len() as f64).sqrt().ceil() as usize;
+    for row in sectors.chunks(columns) {
+        let line: Vec<String> = row.iter()
+            .map(|(sector, faction, ..)| {
+                let initial = faction.as_ref().and_then(|f| f.chars().next()).unwrap_or('.');
+                format!("{sector:>6} [{initial}]")
+            })
+            .collect();
+        println!("{}", line.join(" "));
+    }
+
+    println!();
+    for (sector, faction, controlled, total) in &sectors {
+        match faction {
+            Some(faction) => println!("{sector}: {faction} controls {controlled} of {total} systems"),
+            None => println!("{sector}: unclaimed ({total} systems)"),
+        }
+    }
+    Ok(())
+}
+
+/// Size of the `FleetSpread` map in characters.
+const FLEET_MAP_WIDTH: usize = 60;
+const FLEET_MAP_HEIGHT: usize = 20;
+
+/// Role group of a ship for the fleet map, with its ANSI color code.
+fn fleet_role_group(role: ShipRole) -> (&'static str, &'static str) {
+    match role {
+        ShipRole::Excavator | ShipRole::Harvester | ShipRole::Refinery => ("MINER", "33"),
+        ShipRole::Hauler | ShipRole::Transport | ShipRole::Command | ShipRole::Carrier => ("TRADER", "32"),
+        ShipRole::Explorer | ShipRole::Surveyor | ShipRole::Satellite => ("EXPLORER", "36"),
+        _ => ("OTHER", "37"),
+    }
+}
+
+async fn fleet_spread(ctx: &Context) -> Result<(), AppError> {
+    let ships = match st_util::list_ships(ctx).await {
+        Ok(ships) => ships,
+        Err(err) => {
+            println!("Error listing ships: {}", describe_api_error(&err));
+            return Ok(());
+        }
+    };
+    let system_symbols: Vec<&str> = ships.iter().map(|ship| ship.nav.system_symbol.as_str()).collect();
+    let coordinates: HashMap<String, (i32, i32)> = sqlx::query_as::<_, (String, i32, i32)>(
+        "SELECT symbol, x, y FROM systems WHERE symbol = ANY($1)")
+        .bind(&system_symbols)
+        .fetch_all(&ctx.db_pool)
+        .await?
+        .into_iter()
+        .map(|(symbol, x, y)| (symbol, (x, y)))
+        .collect();
+
+    let placed: Vec<(&Ship, (i32, i32))> = ships.iter()
+        .filter_map(|ship| coordinates.get(&ship.nav.system_symbol).map(|position| (ship, *position)))
+        .collect();
+    if placed.is_empty() {
+        println!("None of the fleet's systems are in the database");
+        return Ok(());
+    }
+
+    let (min_x, max_x) = placed.iter().fold((i32::MAX, i32::MIN), |(lo, hi), (_, (x, _))| (lo.min(*x), hi.max(*x)));
+    let (min_y, max_y) = placed.iter().fold((i32::MAX, i32::MIN), |(lo, hi), (_, (_, y))| (lo.min(*y), hi.max(*y)));
+    let scale = |value: i32, min: i32, max: i32, cells: usize| -> usize {
+        if max == min {
+            return cells / 2;
+        }
+        (f64::from(value - min) / f64::from(max - min) * (cells - 1) as f64).round() as usize
+    };
+
+    // Each cell keeps the first ship placed in it and how many ships share it.
+    let mut grid: Vec<Vec<Option<(ShipRole, usize)>>> = vec![vec![None; FLEET_MAP_WIDTH]; FLEET_MAP_HEIGHT];
+    for (ship, (x, y)) in &placed {
+        let column = scale(*x, min_x, max_x, FLEET_MAP_WIDTH);
+        let row = scale(*y, min_y, max_y, FLEET_MAP_HEIGHT);
+        let cell = &mut grid[row][column];
+        match cell {
+            Some((_, count)) => *count += 1,
+            None => *cell = Some((ship.registration.role, 1)),
+        }
+    }
+
+    println!("+{}+", "-".repeat(FLEET_MAP_WIDTH));
+    for row in &grid {
+        let line: String = row.iter()
+            .map(|cell| match cell {
+                None => " ".to_string(),
+                Some((role, count)) => {
+                    let (group, color) = fleet_role_group(*role);
+                    let marker = if *count > 1 { char::from_digit((*count).min(9) as u32, 10).unwrap_or('+') } else { group.chars().next().unwrap_or('?') };
+                    format!("\x1b[{color}m{marker}\x1b[0m")
+                }
+            })
+            .collect();
+        println!("|{line}|");
+    }
+    println!("+{}+", "-".repeat(FLEET_MAP_WIDTH));
+    println!("x {min_x}..{max_x}, y {min_y}..{max_y}. Digits mark several ships in one spot.");
+    for (group, color) in [("MINER", "33"), ("TRADER", "32"), ("EXPLORER", "36"), ("OTHER", "37")] {
+        print!("\x1b[{color}m{}\x1b[0m {group}  ", group.chars().next().unwrap_or('?'));
+    }
+    println!();
+
+    println!();
+    for (ship, (x, y)) in &placed {
+        let (group, color) = fleet_role_group(ship.registration.role);
+        println!(
+            "\x1b[{color}m{:<20}\x1b[0m {:<12} {group:<9} {:<12} ({x}, {y})",
+            ship.symbol, ship.registration.role.to_string(), ship.nav.system_symbol
+        );
+    }
+    let unplaced = ships.len() - placed.len();
+    if unplaced > 0 {
+        println!("{unplaced} ships are in systems missing from the database");
+    }
+    Ok(())
+}
+
+/// Icon shown next to waypoints with a marketplace.
+const MARKETPLACE_ICON: char = '🏪';
+
+/// Icon for each waypoint type in listings.
+/// Gravity wells get the star, since they mark the system's centre, and orbital stations the fuel pump.
+const fn waypoint_type_icon(waypoint_type: WaypointType) -> char {
+    match waypoint_type {
+        WaypointType::Planet => '🪐',
+        WaypointType::GasGiant => '🌕',
+        WaypointType::Moon => '🌙',
+        WaypointType::OrbitalStation => '⛽',
+        WaypointType::JumpGate => '🌀',
+        WaypointType::AsteroidField => '🪨',
+        WaypointType::Nebula => '🌫',
+        WaypointType::DebrisField => '🚀',
+        WaypointType::GravityWell => '⭐',
+    }
+}
+
+/// [`waypoint_type_icon`] for a type stored by its API name, or `?` if it isn't one.
+fn waypoint_type_name_icon(waypoint_type: &str) -> char {
+    serde_json::from_value(serde_json::Value::String(waypoint_type.to_string())).map_or('?', waypoint_type_icon)
+}
+
+/// [`MARKETPLACE_ICON`] if `is_marketplace` is known to be true, and a space otherwise.
+fn marketplace_icon(is_marketplace: Option<bool>) -> char {
+    if is_marketplace == Some(true) { MARKETPLACE_ICON } else { ' ' }
+}
+
+/// Number of changed waypoint types listed by `ResetDelta`.
+const RESET_DELTA_TYPE_CHANGES_SHOWN: i64 = 10;
+
+#[derive(sqlx::FromRow)]
+struct ResetDeltaCounts {
+    new_systems: i64,
+    removed_systems: i64,
+    new_waypoints: i64,
+    removed_waypoints: i64,
+}
+
+async fn reset_delta(ctx: &Context) -> Result<(), AppError> {
+    let archived_at: Option<String> = sqlx::query_scalar("SELECT value FROM systems_meta WHERE key = 'last_archive'")
+        .fetch_optional(&ctx.db_pool)
+        .await?;
+    let Some(archived_at) = archived_at else {
+        println!("No earlier universe archived yet. One is kept when a server reset is detected.");
+        return Ok(());
+    };
+
+    let counts: ResetDeltaCounts = sqlx::query_as(
+        "SELECT
+            (SELECT COUNT(*) FROM systems s WHERE NOT EXISTS (SELECT FROM systems_archive a WHERE a.symbol = s.symbol)) AS new_systems,
+            (SELECT COUNT(*) FROM systems_archive a WHERE NOT EXISTS (SELECT FROM systems s WHERE s.symbol = a.symbol)) AS removed_systems,
+            (SELECT COUNT(*) FROM waypoints w WHERE NOT EXISTS (SELECT FROM waypoints_archive a WHERE a.symbol = w.symbol)) AS new_waypoints,
+            (SELECT COUNT(*) FROM waypoints_archive a WHERE NOT EXISTS (SELECT FROM waypoints w WHERE w.symbol = a.symbol)) AS removed_waypoints")
+        .fetch_one(&ctx.db_pool)
+        .await?;
+    println!("Compared to the universe archived at {archived_at}:");
+    println!("Systems:   {} new, {} removed", counts.new_systems, counts.removed_systems);
+    println!("Waypoints: {} new, {} removed", counts.new_waypoints, counts.removed_waypoints);
+
+    let type_changes: Vec<(String, Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT w.symbol, a.type, w.type FROM waypoints w JOIN waypoints_archive a ON a.symbol = w.symbol
+        WHERE a.type IS DISTINCT FROM w.type ORDER BY w.symbol")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    println!("{} waypoint(s) changed type", type_changes.len());
+    for (symbol, before, after) in type_changes.iter().take(RESET_DELTA_TYPE_CHANGES_SHOWN as usize) {
+        println!("  {symbol:<20} {} -> {}", before.as_deref().unwrap_or("?"), after.as_deref().unwrap_or("?"));
+    }
+
+    let territories: Vec<(String, i64, i64)> = sqlx::query_as(
+        "SELECT COALESCE(n.faction, a.faction), COALESCE(a.systems, 0), COALESCE(n.systems, 0)
+        FROM (SELECT controlling_faction AS faction, COUNT(*) AS systems FROM systems
+            WHERE controlling_faction IS NOT NULL GROUP BY 1) n
+        FULL JOIN (SELECT controlling_faction AS faction, COUNT(*) AS systems FROM systems_archive
+            WHERE controlling_faction IS NOT NULL GROUP BY 1) a ON a.faction = n.faction
+        ORDER BY 3 DESC, 1")
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    println!();
+    println!("{:<16} {:>8} {:>8} {:>8}", "FACTION", "BEFORE", "NOW", "CHANGE");
+    for (faction, before, now) in territories {
+        let note = if before == 0 { " new" } else if now == 0 { " gone" } else { "" };
+        println!("{faction:<16} {before:>8} {now:>8} {:>+8}{note}", now - before);
+    }
+    Ok(())
+}
+
+fn format_known_flag(flag: Option<bool>) -> &'static str {
+    match flag {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "unknown",
+    }
+}
+
+async fn economic_zone_analysis(ctx: &Context) -> Result<(), AppError> {
+    let center_symbol = prompt_waypoint_symbol()?;
+    let radius: f64 = CustomType::new("Enter radius").prompt()?;
+
+    let zone = match st_util::get_economic_zone(ctx, &center_symbol, radius).await {
+        Ok(zone) => zone,
+        Err(err) => {
+            tracing::error!(error = ?err, "getting economic zone failed");
+            return Ok(());
+        }
+    };
+    let Some(center) = zone.iter().find(|waypoint| waypoint.symbol == center_symbol) else {
+        println!("{center_symbol} is not in the waypoints table");
+        return Ok(());
+    };
+
+    println!("Economic zone of {center_symbol} in {}", center.system_symbol);
+    println!("   {:<20} {:<16} {:>8} {:<12} SHIPYARD", "SYMBOL", "TYPE", "DISTANCE", "MARKETPLACE");
+    for waypoint in &zone {
+        let distance = f64::from(waypoint.x - center.x).hypot(f64::from(waypoint.y - center.y));
+        println!(
+            "{} {:<20} {:<16} {distance:>8.1} {:<12} {}",
+            waypoint_type_name_icon(&waypoint.waypoint_type),
+            waypoint.symbol,
+            waypoint.waypoint_type,
+            format_known_flag(waypoint.is_marketplace),
+            format_known_flag(waypoint.is_shipyard)
+        );
+    }
+
+    let marketplaces = zone.iter().filter(|waypoint| waypoint.is_marketplace == Some(true)).count();
+    let resource_sites = zone.iter().filter(|waypoint| waypoint.waypoint_type == "ASTEROID_FIELD").count();
+    println!("{} waypoints within {radius}: {marketplaces} known marketplaces, {resource_sites} asteroid fields", zone.len());
+    Ok(())
+}
+
+/// Prints the goods leaving `node` as a tree, descending into each importer.
+/// Nodes already drawn elsewhere are marked instead of being expanded again, which also breaks cycles.
+fn print_production_tree(graph: &st_util::ProductionGraph, node: &str, prefix: &str, drawn: &mut HashSet<String>) {
+    let edges: Vec<_> = graph.outgoing(node).collect();
+    for (i, edge) in edges.iter().enumerate() {
+        let last = i + 1 == edges.len();
+        let (branch, indent) = if last { ("└─", "   ") } else { ("├─", "│  ") };
+        if drawn.insert(edge.to.clone()) {
+            println!("{prefix}{branch} {} ──▶ {} [{}]", edge.trade_symbol, edge.to, graph.role(&edge.to));
+            print_production_tree(graph, &edge.to, &format!("{prefix}{indent}"), drawn);
+        } else {
+            println!("{prefix}{branch} {} ──▶ {} (see above)", edge.trade_symbol, edge.to);
+        }
+    }
+}
+
+async fn production_chain(ctx: &Context) -> Result<(), AppError> {
+    let system_symbol = prompt_system_symbol(ctx).await?;
+    println!("Fetching markets in {system_symbol}...");
+
+    let graph = match st_util::build_production_graph(ctx, &system_symbol).await {
+        Ok(graph) => graph,
+        Err(err) => {
+            println!("Error listing waypoints: {}", describe_api_error(&err));
+            return Ok(());
+        }
+    };
+    if graph.nodes.is_empty() {
+        println!("No marketplaces with market data in {system_symbol}");
+        return Ok(());
+    }
+
+    // Start from producers, then pick up anything only reachable through a cycle.
+    let mut drawn = HashSet::new();
+    let roots = graph.nodes.iter()
+        .filter(|node| graph.role(node) == "producer")
+        .chain(graph.nodes.iter().filter(|node| graph.role(node) != "producer"));
+    for root in roots {
+        if graph.outgoing(root).next().is_none() || !drawn.insert(root.clone()) {
+            continue;
+        }
+        println!("{root} [{}]", graph.role(root));
+        print_production_tree(&graph, root, "", &mut drawn);
+    }
+
+    for role in ["producer", "processor", "consumer", "isolated"] {
+        let nodes: Vec<&str> = graph.nodes.iter().filter(|node| graph.role(node) == role).map(String::as_str).collect();
+        if !nodes.is_empty() {
+            println!("{role}s: {}", nodes.join(", "));
+        }
+    }
+    if !graph.unavailable.is_empty() {
+        println!("Could not fetch markets: {}", graph.unavailable.join(", "));
+    }
+    Ok(())
+}
+
+/// Tables larger than this many megabytes get a pruning suggestion, unless overridden by `DB_TABLE_SIZE_WARN_MB`.
+const DEFAULT_TABLE_SIZE_WARN_MB: i64 = 100;
+
+fn prompt_csv_directory() -> InquireResult<PathBuf> {
+    let directory = Text::new("Directory")
+        .with_default(".")
+        .prompt()?;
+    Ok(PathBuf::from(directory.trim()))
+}
+
+async fn export_csv(ctx: &Context) -> Result<(), AppError> {
+    let directory = prompt_csv_directory()?;
+    match csv_io::export_csv(ctx, &directory).await {
+        Ok((systems, waypoints)) => println!(
+            "Wrote {systems} systems to {} and {waypoints} waypoints to {}",
+            directory.join(csv_io::SYSTEMS_FILE).display(), directory.join(csv_io::WAYPOINTS_FILE).display()
+        ),
+        Err(err) => println!("Export failed: {err}"),
+    }
+    Ok(())
+}
+
+async fn import_csv(ctx: &Context) -> Result<(), AppError> {
+    let directory = prompt_csv_directory()?;
+    if !Confirm::new("Replace the stored systems, waypoints and waypoint traits?").with_default(false).prompt()? {
+        return Ok(());
+    }
+    match csv_io::import_csv(ctx, &directory).await {
+        Ok((systems, waypoints)) => println!("Imported {systems} systems and {waypoints} waypoints"),
+        Err(err) => println!("Import failed: {err}"),
+    }
+    Ok(())
+}
+
+async fn database_size(ctx: &Context) -> Result<(), AppError> {
+    let warn_mb = context::parse_env("DB_TABLE_SIZE_WARN_MB", DEFAULT_TABLE_SIZE_WARN_MB);
+
+    let tables: Vec<(String, i64, i64, String)> = sqlx::query_as(
+        "SELECT relname::text, n_live_tup, pg_total_relation_size(relid), pg_size_pretty(pg_total_relation_size(relid))
+        FROM pg_stat_user_tables WHERE schemaname = 'public' ORDER BY 3 DESC"
+        )
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    println!("{:<24} {:>12} {:>14} {:>10}", "TABLE", "ROWS (EST.)", "SIZE (BYTES)", "SIZE");
+    let mut oversized = Vec::new();
+    for (table, rows, bytes, pretty) in &tables {
+        let marker = if *bytes > warn_mb * 1024 * 1024 {
+            oversized.push(table);
+            " !"
+        } else {
+            ""
+        };
+        println!("{table:<24} {rows:>12} {bytes:>14} {pretty:>10}{marker}");
+    }
+    let total: i64 = tables.iter().map(|(_, _, bytes, _)| bytes).sum();
+    println!("Total: {total} bytes");
+
+    for table in oversized {
+        println!("{table} is over {warn_mb} MB: consider deleting stale rows, or dropping it so it is re-fetched on next start");
+    }
+    Ok(())
+}
+
+async fn game_news(ctx: &Context) -> Result<(), AppError> {
+    let announcements: Vec<(String, String, String)> = sqlx::query_as(
+        "SELECT title, body, to_char(first_seen, 'YYYY-MM-DD HH24:MI') FROM game_events
+        WHERE kind = 'announcement' ORDER BY first_seen DESC LIMIT 20"
+        )
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    if announcements.is_empty() {
+        println!("No announcements");
+    }
+    for (title, body, first_seen) in announcements {
+        println!("[{first_seen}] {title}\n    {body}");
+    }
+
+    let resets: Vec<(String, String)> = sqlx::query_as(
+        "SELECT DISTINCT ON (kind) kind, title FROM game_events WHERE kind IN ('reset', 'next_reset') ORDER BY kind, first_seen DESC"
+        )
+        .fetch_all(&ctx.db_pool)
+        .await?;
+    for (kind, date) in resets {
+        match kind.as_str() {
+            "reset" => println!("Last server reset: {date}"),
+            _ => println!("Next server reset: {date}"),
+        }
+    }
+    Ok(())
+}
+
+/// Prints the error a menu action stopped with. Cancelling a prompt with Esc is not reported.
+fn report_error(result: Result<(), impl Into<AppError>>) {
+    match result.map_err(Into::into) {
+        Ok(()) | Err(AppError::Prompt(InquireError::OperationCanceled)) => {}
+        Err(err) => println!("Error: {err}"),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    if env::args().nth(1).as_deref() == Some("--setup") {
+        report_error(setup::run().await);
+        return;
+    }
+
+    //Setup
+    setup_dotenv();
+    let ctx = &match Context::from_profile().await {
+        Ok(ctx) => ctx,
+        Err(InquireError::OperationCanceled | InquireError::OperationInterrupted) => return,
+        Err(err) => {
+            tracing::error!(error = %err, "selecting agent profile failed");
+            process::exit(1);
+        }
+    };
+    if let Err(err) = sqlx::migrate!("./migrations").run(&ctx.db_pool).await {
+        tracing::error!(error = %err, "database migration failed");
+        process::exit(1);
+    }
+    if let Err(err) = ensure_systems_data(ctx).await {
+        tracing::error!(error = %err, "loading systems data failed");
+        process::exit(1);
+    }
+    if let Err(err) = ctx.query_cache.load_waypoint_graph(&ctx.db_pool).await {
+        tracing::error!(error = ?err, "building waypoint index failed");
+    }
+    start_status_poll_task(ctx);
+    let database_health = background::start_database_health_task(ctx);
+    let market_refresh_secs = context::parse_env("MARKET_REFRESH_SECS", background::DEFAULT_MARKET_REFRESH_SECS);
+    background::start_market_refresh_task(ctx, Duration::from_secs(market_refresh_secs), database_health.clone());
+    let critical_resource_poll_secs = context::parse_env("CRITICAL_RESOURCE_POLL_SECS", background::DEFAULT_CRITICAL_RESOURCE_POLL_SECS);
+    background::start_critical_resource_task(ctx, Duration::from_secs(critical_resource_poll_secs), database_health.clone());
+    let survey_warn_mins = context::parse_env("SURVEY_WARN_MINS", background::DEFAULT_SURVEY_WARN_MINS);
+    background::start_survey_refresh_task(ctx, survey_warn_mins, database_health);
+    
+    let mut show_status_bar = env::var("SHOW_STATUS_BAR").is_ok_and(|value| value == "true");
+    loop {
+        if show_status_bar {
+            print_status_bar(ctx).await;
+        }
+        let choice = match prompt_main_menu() {
+            Ok(Some(MenuChoice::Help)) => interactive_help(),
+            choice => choice,
+        };
+        match choice {
+            Err(err) => {
+                tracing::error!(error = %err, "prompt failed");
+            }
+            Ok(None) => {}
+            Ok(Some(choice)) => match choice {
+                MenuChoice::GetAgent => get_agent(ctx).await,
+                MenuChoice::ListContracts => list_contracts(ctx).await,
+                MenuChoice::SyncContracts => report_error(sync_contracts(ctx).await),
+                MenuChoice::ViewContractDeliveryStatus => view_contract_delivery_status(ctx).await,
+                MenuChoice::ListShips => list_ships(ctx).await,
+                MenuChoice::ListWaypoints => report_error(list_waypoints(ctx).await),
+                MenuChoice::GetWaypoint => report_error(get_waypoint(ctx).await),
+                MenuChoice::SearchWaypointsByTrait => report_error(search_waypoints_by_trait(ctx).await),
+                MenuChoice::FindNearestMarketplace => report_error(find_nearest_marketplace(ctx).await),
+                MenuChoice::BuildJumpGateGraph => report_error(build_jump_gate_graph(ctx).await),
+                MenuChoice::FindRoute => report_error(find_route(ctx).await),
+                MenuChoice::PopulateDistanceCache => report_error(populate_distance_cache(ctx).await),
+                MenuChoice::CheckDataIntegrity => report_error(check_data_integrity(ctx).await),
+                MenuChoice::BookmarkSystem => report_error(bookmark_system(ctx).await),
+                MenuChoice::ListBookmarks => report_error(list_bookmarks(ctx).await),
+                MenuChoice::NicknameSystem => report_error(nickname_system(ctx).await),
+                MenuChoice::FactionMap => report_error(faction_map(ctx).await),
+                MenuChoice::FleetSpread => report_error(fleet_spread(ctx).await),
+                MenuChoice::ResetDelta => report_error(reset_delta(ctx).await),
+                MenuChoice::EconomicZoneAnalysis => report_error(economic_zone_analysis(ctx).await),
+                MenuChoice::ProductionChain => report_error(production_chain(ctx).await),
+                MenuChoice::DatabaseSize => report_error(database_size(ctx).await),
+                MenuChoice::ExportCSV => report_error(export_csv(ctx).await),
+                MenuChoice::ImportCSV => report_error(import_csv(ctx).await),
+                MenuChoice::GameNews => report_error(game_news(ctx).await),
+                MenuChoice::ShipStatus => report_error(ship_status(ctx).await),
+                MenuChoice::NavigateShip => report_error(navigate_ship(ctx).await),
+                MenuChoice::OrbitDock => report_error(toggle_orbit_dock(ctx).await),
+                MenuChoice::FormationNavigate => report_error(formation_navigate(ctx).await),
+                MenuChoice::RefuelShip => report_error(refuel_ship(ctx).await),
+                MenuChoice::SurveyWaypoint => report_error(survey_waypoint(ctx).await),
+                MenuChoice::ExtractResources => report_error(extract_resources(ctx).await),
+                MenuChoice::TransferCargo => report_error(transfer_cargo(ctx).await),
+                MenuChoice::PurchaseShip => report_error(purchase_ship(ctx).await),
+                MenuChoice::AcceptContract => report_error(accept_contract(ctx).await),
+                MenuChoice::FulfillContract => report_error(fulfill_contract(ctx).await),
+                MenuChoice::BuyGoods => report_error(buy_goods(ctx).await),
+                MenuChoice::SellGoods => report_error(sell_goods(ctx).await),
+                MenuChoice::UpdateMarketPrices => report_error(update_market_prices(ctx).await),
+                MenuChoice::RefreshNearbyMarkets => report_error(refresh_nearby_markets_menu(ctx).await),
+                MenuChoice::FindBestTrade => find_best_trade(ctx).await,
+                MenuChoice::ListWaypointMarkets => report_error(list_waypoint_markets(ctx).await),
+                MenuChoice::ManageWatchlist => report_error(manage_watchlist(ctx).await),
+                MenuChoice::WatchlistPrices => report_error(watchlist_prices(ctx).await),
+                MenuChoice::ManageReserves => report_error(manage_reserves(ctx).await),
+                MenuChoice::ToggleStatusBar => {
+                    show_status_bar = !show_status_bar;
+                    println!("Status bar {}", if show_status_bar { "on" } else { "off" });
+                }
+                MenuChoice::Help => {}
+                MenuChoice::Exit => {
+                    println!("Bye!");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use spacedust::models::{SystemType, SystemWaypoint, WaypointType};
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::cache::{ApiCache, QueryCache};
+
+    fn test_context(db_pool: PgPool) -> Context {
+        Context {
+            configuration: Default::default(),
+            db_pool,
+            api_cache: ApiCache::new(Duration::from_secs(60)),
+            query_cache: QueryCache::default(),
+        }
+    }
+
+    #[test]
+    fn help_matches_example_questions() {
+        assert_eq!(match_help_question("sell cargo"), Some(MenuChoice::SellGoods));
+        assert_eq!(match_help_question("Where can I find fuel?"), Some(MenuChoice::FindNearestMarketplace));
+        assert_eq!(match_help_question("buy ship"), Some(MenuChoice::PurchaseShip));
+        assert_eq!(match_help_question("buy"), Some(MenuChoice::BuyGoods));
+    }
+
+    #[test]
+    fn help_breaks_ties_by_keyword_order() {
+        assert_eq!(match_help_question("fly to the map"), Some(MenuChoice::NavigateShip));
+        assert_eq!(match_help_question("map then fly"), Some(MenuChoice::NavigateShip));
+    }
+
+    #[sqlx::test]
+    async fn destination_suggestions_treat_input_literally(pool: PgPool) {
+        let ctx = test_context(pool);
+        sqlx::query("INSERT INTO systems(symbol, sector_symbol, type, x, y, factions) VALUES ('X1-AB', 'X1', 'RED_STAR', 0, 0, '{}')")
+            .execute(&ctx.db_pool)
+            .await
+            .unwrap();
+
+        match resolve_destination_input(&ctx, "x1-a").await {
+            Err(DestinationError::NotFound { suggestions, .. }) => assert_eq!(suggestions, vec!["X1-AB"]),
+            other => panic!("expected a suggestion, got {other:?}"),
+        }
+        match resolve_destination_input(&ctx, "X1_A%").await {
+            Err(DestinationError::NotFound { suggestions, .. }) => assert!(suggestions.is_empty(), "{suggestions:?}"),
+            other => panic!("expected no suggestions, got {other:?}"),
+        }
+    }
+
+    #[sqlx::test]
+    async fn upsert_waypoints_chunks_large_systems(pool: PgPool) {
+        let ctx = test_context(pool);
+        let waypoint_count = BIND_LIMIT / 5 + 10;
+        let waypoints = (0..waypoint_count)
+            .map(|i| SystemWaypoint::new(format!("X1-TEST-W{i}"), WaypointType::AsteroidField, 1, 2))
+            .collect();
+        let systems = [
+            System::new("X1-TEST".to_string(), "X1".to_string(), SystemType::RedStar, 0, 0, waypoints, Vec::new()),
+            System::new("X1-EMPTY".to_string(), "X1".to_string(), SystemType::WhiteDwarf, 5, 5, Vec::new(), Vec::new()),
+        ];
+
+        upsert_waypoints(&ctx, &systems).await.unwrap();
+
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM waypoints WHERE system_symbol = 'X1-TEST'")
+            .fetch_one(&ctx.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, i64::try_from(waypoint_count).unwrap());
+    }
+}
@@ -2,7 +2,7 @@
 
 use std::time::Duration;
 
-use reqwest::{Request, Response};
+use reqwest::{header::RETRY_AFTER, Request, Response, StatusCode};
 use task_local_extensions::Extensions;
 use tokio::{
     sync::{Mutex, Semaphore, SemaphorePermit},
@@ -35,7 +35,32 @@ impl ReturnPermit {
 /// Locking ensures that waiting periods are sequential.
 static RETURN: Mutex<ReturnPermit> = Mutex::const_new(ReturnPermit);
 
+/// Maximum number of attempts for a request that keeps getting HTTP 429 responses.
+static MAX_ATTEMPTS: u32 = 5;
+
+/// Back-off before the first retry when the response has no usable `Retry-After` header.
+static INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Upper bound for the exponential back-off.
+static MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long to wait before retrying a throttled request.
+/// Uses the `Retry-After` header (in seconds) if present, otherwise exponential back-off.
+fn retry_delay(response: &Response, retries: u32) -> Duration {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map_or_else(
+            || INITIAL_BACKOFF.saturating_mul(2_u32.saturating_pow(retries)).min(MAX_BACKOFF),
+            Duration::from_secs_f64,
+        )
+}
+
 /// Middleware to enforce rate-limiting for the SpaceTraders API.
+/// Requests that still get throttled (HTTP 429) are transparently retried.
 #[derive(Default)]
 pub struct RateLimitMiddleware;
 
@@ -43,21 +68,44 @@ pub struct RateLimitMiddleware;
 impl reqwest_middleware::Middleware for RateLimitMiddleware {
     async fn handle(
         &self,
-        reqest: Request,
+        request: Request,
         extensions: &mut Extensions,
         next: reqwest_middleware::Next<'_>,
     ) -> reqwest_middleware::Result<Response> {
-        // Acquire a permit, yield if burst limit attained.
-        let permit = REQUEST_SEMAPHORE.acquire().await.unwrap();
-        let result = next.run(reqest, extensions).await;
-
-        // Return permit to the pool after an appropriate timeout.
-        tokio::spawn(async move {
-            let mut return_permit = RETURN.lock().await;
-            return_permit.return_permit(permit).await;
-            drop(return_permit);
-        });
-
-        result
+        let mut request = request;
+        let mut retries = 0;
+        loop {
+            // Requests with streaming bodies cannot be cloned, and so cannot be retried.
+            let retry_request = request.try_clone();
+
+            // Acquire a permit, yield if burst limit attained.
+            let permit = REQUEST_SEMAPHORE.acquire().await.unwrap();
+            let result = next.clone().run(request, extensions).await;
+
+            // Return permit to the pool after an appropriate timeout.
+            tokio::spawn(async move {
+                let mut return_permit = RETURN.lock().await;
+                return_permit.return_permit(permit).await;
+                drop(return_permit);
+            });
+
+            let response = match result {
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => response,
+                other => return other,
+            };
+            let Some(retry_request) = retry_request else {
+                return Ok(response);
+            };
+            if retries + 1 >= MAX_ATTEMPTS {
+                tracing::warn!(retries, url = %response.url(), "still rate limited, giving up");
+                return Ok(response);
+            }
+
+            let delay = retry_delay(&response, retries);
+            retries += 1;
+            tracing::warn!(retries, delay_ms = delay.as_millis(), url = %response.url(), "rate limited, retrying");
+            sleep(delay).await;
+            request = retry_request;
+        }
     }
 }
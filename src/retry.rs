@@ -0,0 +1,156 @@
+use std::collections::hash_map::RandomState;
+use std::error::Error as _;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::time::Duration;
+
+use reqwest::{Method, Request, Response, StatusCode};
+use task_local_extensions::Extensions;
+use tokio::time::sleep;
+
+use crate::context::parse_env;
+
+const DEFAULT_RETRY_MAX: u32 = 3;
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 500;
+
+/// Whether a response status is a transient server error worth retrying.
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::INTERNAL_SERVER_ERROR | StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// How a request failed transiently, which decides whether it is safe to send again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// The connection could not be made, so the request never reached the server.
+    Connect,
+    /// A transient status, a timeout or a dropped connection. The server may already have processed the request.
+    AfterSend,
+}
+
+/// Whether sending `method` twice has the same effect as sending it once.
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::PUT | Method::DELETE)
+}
+
+/// Whether a request may be retried after `failure`. Anything other than a failed connection is only retried
+/// for idempotent methods, so a POST such as a purchase is never sent twice.
+fn may_retry(method: &Method, failure: Failure) -> bool {
+    failure == Failure::Connect || is_idempotent(method)
+}
+
+/// Whether a request failure is a timeout or a dropped connection rather than a problem with the request itself.
+fn transient_error_failure(err: &reqwest_middleware::Error) -> Option<Failure> {
+    let reqwest_middleware::Error::Reqwest(err) = err else {
+        return None;
+    };
+    if err.is_connect() {
+        return Some(Failure::Connect);
+    }
+    if err.is_timeout() {
+        return Some(Failure::AfterSend);
+    }
+    let mut source = err.source();
+    while let Some(cause) = source {
+        if let Some(io_err) = cause.downcast_ref::<io::Error>() {
+            if matches!(io_err.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::TimedOut) {
+                return Some(Failure::AfterSend);
+            }
+        }
+        source = cause.source();
+    }
+    None
+}
+
+/// Middleware to retry requests that hit transient server errors (HTTP 500, 502, 503, 504) or network failures.
+/// Non-idempotent requests are only retried if they could not connect. Waits with exponential back-off and jitter between attempts.
+pub struct RetryMiddleware {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryMiddleware {
+    /// Create a middleware configured from `RETRY_MAX` and `RETRY_BASE_DELAY_MS`.
+    pub fn from_env() -> Self {
+        let max_retries = parse_env("RETRY_MAX", DEFAULT_RETRY_MAX);
+        let base_delay_ms = parse_env("RETRY_BASE_DELAY_MS", DEFAULT_RETRY_BASE_DELAY_MS);
+        RetryMiddleware { max_retries, base_delay: Duration::from_millis(base_delay_ms) }
+    }
+
+    /// Back-off before retry number `retries` (starting at 0), with up to 50% random jitter added.
+    fn delay(&self, retries: u32) -> Duration {
+        let backoff = self.base_delay.saturating_mul(2_u32.saturating_pow(retries));
+        let jitter_fraction = (RandomState::new().build_hasher().finish() % 1000) as f64 / 2000.0;
+        backoff.mul_f64(1.0 + jitter_fraction)
+    }
+}
+
+#[async_trait::async_trait]
+impl reqwest_middleware::Middleware for RetryMiddleware {
+    async fn handle(
+        &self,
+        request: Request,
+        extensions: &mut Extensions,
+        next: reqwest_middleware::Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let mut request = request;
+        let mut retries = 0;
+        loop {
+            // Requests with streaming bodies cannot be cloned, and so cannot be retried.
+            let retry_request = request.try_clone();
+            let url = request.url().clone();
+            let method = request.method().clone();
+            let result = next.clone().run(request, extensions).await;
+
+            let (failure, reason) = match &result {
+                Ok(response) if is_transient_status(response.status()) => (Failure::AfterSend, response.status().to_string()),
+                Err(err) => match transient_error_failure(err) {
+                    Some(failure) => (failure, err.to_string()),
+                    None => return result,
+                },
+                _ => return result,
+            };
+            if !may_retry(&method, failure) {
+                tracing::warn!(%method, %url, reason, "request may have been processed, not retrying");
+                return result;
+            }
+            let Some(retry_request) = retry_request else {
+                return result;
+            };
+            if retries >= self.max_retries {
+                tracing::warn!(retries, %url, reason, "request still failing, giving up");
+                return result;
+            }
+
+            let delay = self.delay(retries);
+            retries += 1;
+            tracing::warn!(retries, delay_ms = delay.as_millis(), %url, reason, "transient failure, retrying");
+            sleep(delay).await;
+            request = retry_request;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_failures_are_retried_for_every_method() {
+        for method in [Method::GET, Method::POST, Method::PATCH, Method::PUT] {
+            assert!(may_retry(&method, Failure::Connect), "{method}");
+        }
+    }
+
+    #[test]
+    fn failures_after_sending_are_only_retried_for_idempotent_methods() {
+        for method in [Method::GET, Method::HEAD, Method::PUT, Method::DELETE] {
+            assert!(may_retry(&method, Failure::AfterSend), "{method}");
+        }
+        for method in [Method::POST, Method::PATCH] {
+            assert!(!may_retry(&method, Failure::AfterSend), "{method}");
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! In-memory spatial index over the waypoints table, for nearest-neighbour and radius queries
+//! without a database round trip per lookup.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::st_util::WaypointRow;
+
+/// The coordinate a node at `depth` splits on: x at even depths, y at odd.
+fn split_coordinate(x: i32, y: i32, depth: usize) -> i32 {
+    if depth.is_multiple_of(2) { x } else { y }
+}
+
+fn distance(waypoint: &WaypointRow, x: i32, y: i32) -> f64 {
+    f64::from(waypoint.x - x).hypot(f64::from(waypoint.y - y))
+}
+
+/// Orders `nodes` so each slice has its median on the split coordinate in the middle,
+/// with lower values before it and higher values after it.
+fn build(nodes: &mut [WaypointRow], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by_key(mid, |waypoint| split_coordinate(waypoint.x, waypoint.y, depth));
+    let (left, right) = nodes.split_at_mut(mid);
+    build(left, depth + 1);
+    build(&mut right[1..], depth + 1);
+}
+
+/// A 2-d tree of the waypoints in one system, stored as a flat slice in tree order.
+pub struct WaypointKdTree {
+    nodes: Vec<WaypointRow>,
+}
+
+impl WaypointKdTree {
+    pub fn new(mut nodes: Vec<WaypointRow>) -> Self {
+        build(&mut nodes, 0);
+        WaypointKdTree { nodes }
+    }
+
+    /// Waypoints within `radius` of `(x, y)`, closest first, with their distances.
+    pub fn within(&self, x: i32, y: i32, radius: f64) -> Vec<(&WaypointRow, f64)> {
+        let mut found = Vec::new();
+        Self::visit_within(&self.nodes, 0, x, y, radius, &mut found);
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found
+    }
+
+    fn visit_within<'a>(nodes: &'a [WaypointRow], depth: usize, x: i32, y: i32, radius: f64, found: &mut Vec<(&'a WaypointRow, f64)>) {
+        if nodes.is_empty() {
+            return;
+        }
+        let mid = nodes.len() / 2;
+        let node = &nodes[mid];
+        let node_distance = distance(node, x, y);
+        if node_distance <= radius {
+            found.push((node, node_distance));
+        }
+        let offset = f64::from(split_coordinate(node.x, node.y, depth) - split_coordinate(x, y, depth));
+        if offset >= -radius {
+            Self::visit_within(&nodes[..mid], depth + 1, x, y, radius, found);
+        }
+        if offset <= radius {
+            Self::visit_within(&nodes[mid + 1..], depth + 1, x, y, radius, found);
+        }
+    }
+
+    /// Up to `limit` waypoints matching `filter` closest to `(x, y)`, closest first, with their distances.
+    /// Single nearest-waypoint lookups are a single SQL query instead, so this is for batches of lookups.
+    #[allow(dead_code)]
+    pub fn nearest(&self, x: i32, y: i32, limit: usize, filter: impl Fn(&WaypointRow) -> bool) -> Vec<(&WaypointRow, f64)> {
+        let mut best = Vec::with_capacity(limit + 1);
+        if limit > 0 {
+            Self::visit_nearest(&self.nodes, 0, x, y, limit, &filter, &mut best);
+        }
+        best
+    }
+
+    fn visit_nearest<'a>(
+        nodes: &'a [WaypointRow],
+        depth: usize,
+        x: i32,
+        y: i32,
+        limit: usize,
+        filter: &impl Fn(&WaypointRow) -> bool,
+        best: &mut Vec<(&'a WaypointRow, f64)>,
+    ) {
+        if nodes.is_empty() {
+            return;
+        }
+        let mid = nodes.len() / 2;
+        let node = &nodes[mid];
+        if filter(node) {
+            let node_distance = distance(node, x, y);
+            let index = best.partition_point(|(_, best_distance)| *best_distance <= node_distance);
+            if index < limit {
+                best.insert(index, (node, node_distance));
+                best.truncate(limit);
+            }
+        }
+        let offset = f64::from(split_coordinate(x, y, depth) - split_coordinate(node.x, node.y, depth));
+        let (near, far) = if offset < 0.0 {
+            (&nodes[..mid], &nodes[mid + 1..])
+        } else {
+            (&nodes[mid + 1..], &nodes[..mid])
+        };
+        Self::visit_nearest(near, depth + 1, x, y, limit, filter, best);
+        if best.len() < limit || best.last().is_some_and(|(_, worst)| offset.abs() <= *worst) {
+            Self::visit_nearest(far, depth + 1, x, y, limit, filter, best);
+        }
+    }
+}
+
+/// Groups `waypoints` by the system they are in.
+fn by_system<'a>(waypoints: impl IntoIterator<Item = &'a WaypointRow>) -> HashMap<&'a str, Vec<&'a WaypointRow>> {
+    let mut systems: HashMap<&str, Vec<&WaypointRow>> = HashMap::new();
+    for waypoint in waypoints {
+        systems.entry(waypoint.system_symbol.as_str()).or_default().push(waypoint);
+    }
+    systems
+}
+
+/// A [`WaypointKdTree`] for every system in the waypoints table.
+/// Waypoint coordinates are relative to their system, so each system gets its own tree.
+/// Clones share the trees.
+#[derive(Clone)]
+pub struct WaypointGraph {
+    systems: HashMap<String, Arc<WaypointKdTree>>,
+}
+
+impl WaypointGraph {
+    pub fn new(waypoints: &[WaypointRow]) -> Self {
+        let systems = by_system(waypoints).into_iter()
+            .map(|(system_symbol, waypoints)| {
+                (system_symbol.to_string(), Arc::new(WaypointKdTree::new(waypoints.into_iter().cloned().collect())))
+            })
+            .collect();
+        WaypointGraph { systems }
+    }
+
+    /// The tree for `system_symbol`, if any of its waypoints are stored.
+    pub fn system(&self, system_symbol: &str) -> Option<Arc<WaypointKdTree>> {
+        self.systems.get(system_symbol).cloned()
+    }
+
+    /// Replaces or adds `waypoints`, rebuilding only the trees of the systems they are in.
+    pub fn update(&mut self, waypoints: &[WaypointRow]) {
+        for (system_symbol, changed) in by_system(waypoints) {
+            let changed_symbols: HashSet<&str> = changed.iter().map(|waypoint| waypoint.symbol.as_str()).collect();
+            let kept = self.systems.get(system_symbol)
+                .map(|tree| tree.nodes.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .filter(|waypoint| !changed_symbols.contains(waypoint.symbol.as_str()));
+            let nodes = kept.chain(changed).cloned().collect();
+            self.systems.insert(system_symbol.to_string(), Arc::new(WaypointKdTree::new(nodes)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waypoint(system_symbol: &str, index: usize, x: i32, y: i32) -> WaypointRow {
+        WaypointRow {
+            symbol: format!("{system_symbol}-W{index}"),
+            waypoint_type: "PLANET".to_string(),
+            system_symbol: system_symbol.to_string(),
+            x,
+            y,
+            is_marketplace: Some(index.is_multiple_of(3)),
+            is_shipyard: None,
+            traits: Vec::new(),
+            faction: None,
+            orbitals: Vec::new(),
+        }
+    }
+
+    /// Waypoints scattered over -500..500 by a fixed linear congruential generator, with some duplicate coordinates.
+    fn scattered(count: usize) -> Vec<WaypointRow> {
+        let mut state: u64 = 12345;
+        let mut next = || {
+            state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            i32::try_from(state >> 33).unwrap() % 1000 - 500
+        };
+        (0..count)
+            .map(|index| if index.is_multiple_of(50) { waypoint("X1-A", index, 0, 0) } else { waypoint("X1-A", index, next(), next()) })
+            .collect()
+    }
+
+    fn brute_force(waypoints: &[WaypointRow], x: i32, y: i32, filter: impl Fn(&WaypointRow) -> bool) -> Vec<(String, f64)> {
+        let mut found: Vec<(String, f64)> = waypoints.iter()
+            .filter(|waypoint| filter(waypoint))
+            .map(|waypoint| (waypoint.symbol.clone(), distance(waypoint, x, y)))
+            .collect();
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found
+    }
+
+    fn distances(found: &[(&WaypointRow, f64)]) -> Vec<f64> {
+        found.iter().map(|(_, distance)| *distance).collect()
+    }
+
+    const QUERIES: [(i32, i32); 5] = [(0, 0), (-500, -500), (499, -12), (37, 260), (2000, 2000)];
+
+    #[test]
+    fn within_matches_brute_force() {
+        let waypoints = scattered(500);
+        let tree = WaypointKdTree::new(waypoints.clone());
+        for (x, y) in QUERIES {
+            for radius in [0.0, 25.0, 150.0, 2000.0] {
+                let found = tree.within(x, y, radius);
+                let expected: Vec<(String, f64)> = brute_force(&waypoints, x, y, |_| true).into_iter()
+                    .filter(|(_, distance)| *distance <= radius)
+                    .collect();
+
+                let mut found_symbols: Vec<&str> = found.iter().map(|(waypoint, _)| waypoint.symbol.as_str()).collect();
+                let mut expected_symbols: Vec<&str> = expected.iter().map(|(symbol, _)| symbol.as_str()).collect();
+                found_symbols.sort_unstable();
+                expected_symbols.sort_unstable();
+                assert_eq!(found_symbols, expected_symbols, "within({x}, {y}, {radius})");
+                assert_eq!(distances(&found), expected.iter().map(|(_, distance)| *distance).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let waypoints = scattered(500);
+        let tree = WaypointKdTree::new(waypoints.clone());
+        let is_marketplace = |waypoint: &WaypointRow| waypoint.is_marketplace == Some(true);
+        for (x, y) in QUERIES {
+            for limit in [0, 1, 7, 600] {
+                let found = tree.nearest(x, y, limit, |_| true);
+                let expected: Vec<f64> = brute_force(&waypoints, x, y, |_| true).into_iter().take(limit).map(|(_, distance)| distance).collect();
+                assert_eq!(distances(&found), expected, "nearest({x}, {y}, {limit})");
+
+                let found = tree.nearest(x, y, limit, is_marketplace);
+                let expected: Vec<f64> = brute_force(&waypoints, x, y, is_marketplace).into_iter().take(limit).map(|(_, distance)| distance).collect();
+                assert!(found.iter().all(|(waypoint, _)| is_marketplace(waypoint)));
+                assert_eq!(distances(&found), expected, "nearest marketplaces({x}, {y}, {limit})");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_tree_finds_nothing() {
+        let tree = WaypointKdTree::new(Vec::new());
+        assert!(tree.within(0, 0, 100.0).is_empty());
+        assert!(tree.nearest(0, 0, 3, |_| true).is_empty());
+    }
+
+    #[test]
+    fn update_only_rebuilds_changed_systems() {
+        let mut graph = WaypointGraph::new(&[waypoint("X1-A", 0, 0, 0), waypoint("X1-A", 1, 10, 0), waypoint("X1-B", 0, 5, 5)]);
+        let untouched = graph.system("X1-B").unwrap();
+
+        graph.update(&[waypoint("X1-A", 1, 100, 0), waypoint("X1-A", 2, 3, 4), waypoint("X1-C", 0, 1, 1)]);
+
+        assert!(Arc::ptr_eq(&untouched, &graph.system("X1-B").unwrap()));
+        let found = graph.system("X1-A").unwrap().nearest(0, 0, 10, |_| true)
+            .into_iter()
+            .map(|(waypoint, distance)| (waypoint.symbol.clone(), distance))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [("X1-A-W0".to_string(), 0.0), ("X1-A-W2".to_string(), 5.0), ("X1-A-W1".to_string(), 100.0)]);
+        assert_eq!(graph.system("X1-C").unwrap().within(1, 1, 0.0).len(), 1);
+    }
+}
@@ -1,28 +1,45 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque},
+    fmt::{self, Debug, Display, Formatter},
+    future::Future,
+    pin::Pin,
+};
+
+use reqwest::StatusCode;
+use serde::Serialize;
+use sqlx::{Postgres, QueryBuilder};
+
 use spacedust::{
     apis::{
+        Error, ResponseContent,
         contracts_api::{get_contracts, GetContractsError},
         factions_api::{get_factions, GetFactionsError},
-        fleet_api::{get_my_ships, GetMyShipsError},
+        fleet_api::{dock_ship, get_my_ships, orbit_ship, DockShipError, GetMyShipsError, OrbitShipError},
         systems_api::{
-            get_system_waypoints, get_systems, GetSystemWaypointsError, GetSystemsError,
+            get_jump_gate, get_market, get_system_waypoints, get_systems, get_waypoint, GetJumpGateError,
+            GetMarketError, GetSystemWaypointsError, GetSystemsError, GetWaypointError,
         },
     },
-    models::{Contract, Faction, Ship, System, Waypoint},
+    models::{
+        market_trade_good::Supply, Contract, Faction, JumpGate, Market, Meta, Ship, ShipNav, ShipNavFlightMode,
+        ShipNavStatus, Survey, SurveyDeposit, System, Waypoint, WaypointTrait,
+    },
 };
 
-use crate::CONFIGURATION;
+use crate::context::Context;
 
 const MAX_PAGE_SIZE: i32 = 20;
 
 macro_rules! impl_list {
     ($(#[$attr:meta])* $vis:vis async fn $name:ident ( $($extra_i:ident : $extra_t:ty,)* ) -> Result<Vec<$out:ty>, Error<$err:ty>> {$func:path}) => {
         $(#[$attr])*
-        $vis async fn $name($($extra_i : $extra_t,)*) -> Result<Vec<$out>, spacedust::apis::Error<$err>>
+        $vis async fn $name(ctx: &Context, $($extra_i : $extra_t,)*) -> Result<Vec<$out>, spacedust::apis::Error<$err>>
         {
             let mut page = 1;
             let mut result: Vec<$out> = Vec::new();
             loop {
-                match $func(&CONFIGURATION, $($extra_i,),* Some(page), Some(MAX_PAGE_SIZE)).await {
+                match $func(&ctx.configuration, $($extra_i,),* Some(page), Some(MAX_PAGE_SIZE)).await {
                     Ok(res) => {
                         let data = res.data;
                         let meta = *(res.meta);
@@ -43,6 +60,103 @@ macro_rules! impl_list {
     };
 }
 
+type PageFuture<T, E> = Pin<Box<dyn Future<Output = Result<(Vec<T>, Meta), E>>>>;
+
+/// Lazily loaded paginated results, fetching a page from the API only when it is first displayed.
+pub struct PaginatedDisplay<T, E> {
+    items: Vec<T>,
+    page: usize,
+    loaded_pages: usize,
+    total: Option<usize>,
+    fetch: Box<dyn Fn(i32) -> PageFuture<T, E>>,
+}
+
+impl<T, E> PaginatedDisplay<T, E> {
+    fn new(fetch: Box<dyn Fn(i32) -> PageFuture<T, E>>) -> Self {
+        PaginatedDisplay { items: Vec::new(), page: 0, loaded_pages: 0, total: None, fetch }
+    }
+
+    /// Zero-indexed number of the current page.
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    /// Total number of pages, known once the first page is loaded.
+    pub fn total_pages(&self) -> Option<usize> {
+        self.total.map(|total| total.div_ceil(MAX_PAGE_SIZE as usize).max(1))
+    }
+
+    /// Whether there is a page after the current one.
+    pub fn has_next_page(&self) -> bool {
+        self.total_pages().is_none_or(|pages| self.page + 1 < pages)
+    }
+
+    /// Get the items of the current page, fetching it if needed.
+    ///
+    /// # Errors
+    /// Propogates any error from fetching the page
+    pub async fn current_page(&mut self) -> Result<&[T], E> {
+        while self.loaded_pages <= self.page && self.has_unloaded_pages() {
+            let page = i32::try_from(self.loaded_pages + 1).unwrap_or(i32::MAX);
+            let (data, meta) = (self.fetch)(page).await?;
+            self.items.extend(data);
+            self.total = Some(meta.total.try_into().unwrap_or(0));
+            self.loaded_pages += 1;
+        }
+        let start = (self.page * MAX_PAGE_SIZE as usize).min(self.items.len());
+        let end = (start + MAX_PAGE_SIZE as usize).min(self.items.len());
+        Ok(&self.items[start..end])
+    }
+
+    /// Advance to the next page (if any) and get its items.
+    ///
+    /// # Errors
+    /// Propogates any error from fetching the page
+    pub async fn next_page(&mut self) -> Result<&[T], E> {
+        if self.has_next_page() {
+            self.page += 1;
+        }
+        self.current_page().await
+    }
+
+    /// Go back to the previous page (if any) and get its items.
+    ///
+    /// # Errors
+    /// Propogates any error from fetching the page
+    pub async fn prev_page(&mut self) -> Result<&[T], E> {
+        self.page = self.page.saturating_sub(1);
+        self.current_page().await
+    }
+
+    fn has_unloaded_pages(&self) -> bool {
+        self.total_pages().is_none_or(|pages| self.loaded_pages < pages)
+    }
+}
+
+/// Lazily page through summaries of all your contracts
+pub fn paginate_contracts(ctx: &Context) -> PaginatedDisplay<ContractSummary, Error<GetContractsError>> {
+    let ctx = ctx.clone();
+    PaginatedDisplay::new(Box::new(move |page| {
+        let ctx = ctx.clone();
+        Box::pin(async move {
+            let res = get_contracts(&ctx.configuration, Some(page), Some(MAX_PAGE_SIZE)).await?;
+            Ok((res.data.iter().map(ContractSummary::from).collect(), *res.meta))
+        })
+    }))
+}
+
+/// Lazily page through all your ships
+pub fn paginate_ships(ctx: &Context) -> PaginatedDisplay<Ship, Error<GetMyShipsError>> {
+    let ctx = ctx.clone();
+    PaginatedDisplay::new(Box::new(move |page| {
+        let ctx = ctx.clone();
+        Box::pin(async move {
+            let res = get_my_ships(&ctx.configuration, Some(page), Some(MAX_PAGE_SIZE)).await?;
+            Ok((res.data, *res.meta))
+        })
+    }))
+}
+
 impl_list!(
     /// Get a list of all waypoints in a given system
     ///
@@ -60,6 +174,7 @@ impl_list!(
     ///
     /// # Errors
     /// Propogates any error from `get_factions`
+    #[allow(dead_code)]
     pub async fn list_factions() -> Result<Vec<Faction>, Error<GetFactionsError>> {
         get_factions
     }
@@ -90,7 +205,857 @@ impl_list!(
     ///
     /// # Errors
     /// Propogates any error from `get_systems`
+    #[allow(dead_code)]
     pub async fn list_systems() -> Result<Vec<System>, Error<GetSystemsError>> {
         get_systems
     }
 );
+
+/// Get a waypoint, from the API cache if it was fetched recently
+///
+/// # Errors
+/// Propogates any error from `get_waypoint`
+pub async fn get_waypoint_cached(ctx: &Context, system_symbol: &str, waypoint_symbol: &str) -> Result<Waypoint, Error<GetWaypointError>> {
+    ctx.api_cache.get_or_fetch(format!("waypoint:{waypoint_symbol}"), || async {
+        Ok(*get_waypoint(&ctx.configuration, system_symbol, waypoint_symbol).await?.data)
+    }).await
+}
+
+/// Get the market at a waypoint, from the API cache if it was fetched recently
+///
+/// # Errors
+/// Propogates any error from `get_market`
+pub async fn get_market_cached(ctx: &Context, system_symbol: &str, waypoint_symbol: &str) -> Result<Market, Error<GetMarketError>> {
+    ctx.api_cache.get_or_fetch(format!("market:{waypoint_symbol}"), || async {
+        Ok(*get_market(&ctx.configuration, system_symbol, waypoint_symbol).await?.data)
+    }).await
+}
+
+/// Get the jump gate at a waypoint, from the API cache if it was fetched recently
+///
+/// # Errors
+/// Propogates any error from `get_jump_gate`
+pub async fn get_jump_gate_cached(ctx: &Context, system_symbol: &str, waypoint_symbol: &str) -> Result<JumpGate, Error<GetJumpGateError>> {
+    ctx.api_cache.get_or_fetch(format!("jump_gate:{waypoint_symbol}"), || async {
+        Ok(*get_jump_gate(&ctx.configuration, system_symbol, waypoint_symbol).await?.data)
+    }).await
+}
+
+/// Why the market at a ship's location couldn't be fetched.
+#[derive(Debug)]
+pub enum ShipMarketError {
+    InTransit { arrival: String },
+    NoMarket { waypoint_symbol: String },
+    Api(Error<GetMarketError>),
+}
+
+impl Display for ShipMarketError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ShipMarketError::InTransit { arrival } => write!(f, "ship is in transit, arriving at {arrival}"),
+            ShipMarketError::NoMarket { waypoint_symbol } => write!(f, "there is no marketplace at {waypoint_symbol}"),
+            ShipMarketError::Api(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// Describes an API error for the user, using the message from the response body when there is one.
+pub fn describe_api_error<T>(err: &Error<T>) -> String {
+    if let Error::ResponseError(response) = err {
+        let message = serde_json::from_str::<serde_json::Value>(&response.content).ok()
+            .and_then(|body| body["error"]["message"].as_str().map(str::to_string));
+        if let Some(message) = message {
+            return format!("{} ({})", message, response.status);
+        }
+    }
+    err.to_string()
+}
+
+/// Why a ship couldn't be moved into orbit or docked.
+#[derive(Debug)]
+pub enum NavStateError {
+    InTransit { arrival: String },
+    Orbit(Error<OrbitShipError>),
+    Dock(Error<DockShipError>),
+}
+
+impl Display for NavStateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            NavStateError::InTransit { arrival } => write!(f, "ship is in transit, arriving at {arrival}"),
+            NavStateError::Orbit(err) => write!(f, "{}", describe_api_error(err)),
+            NavStateError::Dock(err) => write!(f, "{}", describe_api_error(err)),
+        }
+    }
+}
+
+/// Move a ship into orbit unless it is already there. Returns its nav state afterwards.
+///
+/// # Errors
+/// Returns an error if the ship is in transit or `orbit_ship` fails
+pub async fn ensure_ship_orbiting(ctx: &Context, ship: &Ship) -> Result<ShipNav, NavStateError> {
+    match ship.nav.status {
+        ShipNavStatus::InOrbit => Ok((*ship.nav).clone()),
+        ShipNavStatus::InTransit => Err(NavStateError::InTransit { arrival: ship.nav.route.arrival.clone() }),
+        ShipNavStatus::Docked => Ok(*orbit_ship(&ctx.configuration, &ship.symbol, 0).await.map_err(NavStateError::Orbit)?.data.nav),
+    }
+}
+
+/// Dock a ship unless it is already docked. Returns its nav state afterwards.
+///
+/// # Errors
+/// Returns an error if the ship is in transit or `dock_ship` fails
+pub async fn ensure_ship_docked(ctx: &Context, ship: &Ship) -> Result<ShipNav, NavStateError> {
+    match ship.nav.status {
+        ShipNavStatus::Docked => Ok((*ship.nav).clone()),
+        ShipNavStatus::InTransit => Err(NavStateError::InTransit { arrival: ship.nav.route.arrival.clone() }),
+        ShipNavStatus::InOrbit => Ok(*dock_ship(&ctx.configuration, &ship.symbol, 0.0).await.map_err(NavStateError::Dock)?.data.nav),
+    }
+}
+
+/// Get the market at a ship's current waypoint, including trade good prices since the ship is present.
+/// Always fetched fresh, then stored in the API cache for other lookups.
+///
+/// # Errors
+/// Returns an error if the ship is in transit, there is no market at its waypoint, or `get_market` fails
+pub async fn get_market_for_ship(ctx: &Context, ship: &Ship) -> Result<Market, ShipMarketError> {
+    if ship.nav.status == ShipNavStatus::InTransit {
+        return Err(ShipMarketError::InTransit { arrival: ship.nav.route.arrival.clone() });
+    }
+    let waypoint_symbol = &ship.nav.waypoint_symbol;
+    match get_market(&ctx.configuration, &ship.nav.system_symbol, waypoint_symbol).await {
+        Ok(res) => {
+            ctx.api_cache.insert(format!("market:{waypoint_symbol}"), (*res.data).clone());
+            Ok(*res.data)
+        }
+        Err(Error::ResponseError(ResponseContent { status, .. })) if status == StatusCode::NOT_FOUND => {
+            Err(ShipMarketError::NoMarket { waypoint_symbol: waypoint_symbol.clone() })
+        }
+        Err(err) => Err(ShipMarketError::Api(err)),
+    }
+}
+
+/// Buying a good at one market and selling it at another, from recorded `market_prices`.
+#[derive(Debug, sqlx::FromRow)]
+pub struct TradeOpportunity {
+    pub trade_symbol: String,
+    pub buy_waypoint: String,
+    pub sell_waypoint: String,
+    pub purchase_price: i32,
+    pub sell_price: i32,
+    pub margin: i32,
+    /// Distance between the markets, if both are in the waypoints table.
+    pub distance: Option<f64>,
+}
+
+/// Get the `limit` most profitable trades between recorded markets in the same system, highest margin first.
+///
+/// # Errors
+/// Propogates any database error
+pub async fn find_best_trades(ctx: &Context, limit: i64) -> Result<Vec<TradeOpportunity>, sqlx::Error> {
+    sqlx::query_as("SELECT b.trade_symbol, b.waypoint_symbol AS buy_waypoint, s.waypoint_symbol AS sell_waypoint,
+                b.purchase_price, s.sell_price, s.sell_price - b.purchase_price AS margin,
+                SQRT(POWER(bw.x - sw.x, 2) + POWER(bw.y - sw.y, 2)) AS distance
+                FROM market_prices b
+                JOIN market_prices s ON s.trade_symbol = b.trade_symbol AND s.waypoint_symbol <> b.waypoint_symbol
+                LEFT JOIN waypoints bw ON bw.symbol = b.waypoint_symbol
+                LEFT JOIN waypoints sw ON sw.symbol = s.waypoint_symbol
+                WHERE s.sell_price > b.purchase_price AND bw.system_symbol IS NOT DISTINCT FROM sw.system_symbol
+                ORDER BY margin DESC
+                LIMIT $1")
+        .bind(limit)
+        .fetch_all(&ctx.db_pool)
+        .await
+}
+
+/// A marketplace and what it was last seen paying for a good.
+#[derive(Debug, sqlx::FromRow)]
+pub struct SellMarket {
+    #[sqlx(flatten)]
+    pub waypoint: WaypointRow,
+    pub sell_price: i32,
+    /// Distance from the ship.
+    pub distance: f64,
+}
+
+/// Get the marketplace in the ship's system paying the most for a good, according to recorded `market_prices`,
+/// among those within `max_distance` of the ship. The ship's own waypoint is included.
+///
+/// # Errors
+/// Propogates any database error
+pub async fn find_best_sell_market(ctx: &Context, ship: &Ship, trade_symbol: &str, max_distance: f64) -> Result<Option<SellMarket>, sqlx::Error> {
+    sqlx::query_as("SELECT w.*, m.sell_price, SQRT(POWER(w.x - o.x, 2) + POWER(w.y - o.y, 2)) AS distance
+                FROM market_prices m
+                JOIN waypoints w ON w.symbol = m.waypoint_symbol
+                JOIN waypoints o ON o.symbol = $1 AND o.system_symbol = w.system_symbol
+                WHERE m.trade_symbol = $2 AND w.is_marketplace
+                    AND SQRT(POWER(w.x - o.x, 2) + POWER(w.y - o.y, 2)) <= $3
+                ORDER BY m.sell_price DESC, distance
+                LIMIT 1")
+        .bind(&ship.nav.waypoint_symbol)
+        .bind(trade_symbol)
+        .bind(max_distance)
+        .fetch_optional(&ctx.db_pool)
+        .await
+}
+
+/// Estimated size of an order a market absorbs before its price moves a tier.
+#[derive(Debug, Clone, Copy)]
+pub struct MarketDepth {
+    pub units: u32,
+    /// Cost of buying all of `units` at the current purchase price.
+    pub cost: i64,
+}
+
+/// Estimate a market's depth for one good from its trade volume and supply level.
+/// Each trade of `trade_volume` units can shift supply, so this counts how many such trades the
+/// current supply level holds before the price changes: one when scarce, up to four when abundant.
+/// It is a rule of thumb, the server does not publish its pricing model.
+pub fn compute_market_depth(trade_volume: u32, supply: Supply, purchase_price: i64) -> MarketDepth {
+    let volumes_per_tier = match supply {
+        Supply::Scarce => 1,
+        Supply::Limited => 2,
+        Supply::Moderate => 3,
+        Supply::Abundant => 4,
+    };
+    let units = trade_volume.saturating_mul(volumes_per_tier);
+    MarketDepth { units, cost: i64::from(units) * purchase_price }
+}
+
+/// Store newly created surveys, ignoring any already stored.
+///
+/// # Errors
+/// Propogates any database error
+pub async fn store_surveys(ctx: &Context, surveys: &[Survey]) -> Result<(), sqlx::Error> {
+    if surveys.is_empty() {
+        return Ok(());
+    }
+    let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+        "INSERT INTO surveys(signature, waypoint_symbol, deposits, expiration, size) "
+        );
+    query_builder.push_values(surveys, |mut b, survey| {
+        b.push_bind(&survey.signature)
+            .push_bind(&survey.symbol)
+            .push_bind(survey.deposits.iter().map(|deposit| deposit.symbol.clone()).collect::<Vec<String>>())
+            .push_bind(&survey.expiration)
+            .push_unseparated("::timestamptz")
+            .push_bind(api_name(&survey.size));
+    });
+    query_builder.push(" ON CONFLICT (signature) DO NOTHING");
+    query_builder.build().execute(&ctx.db_pool).await?;
+    Ok(())
+}
+
+/// Delete expired surveys, then get the remaining ones for a waypoint, soonest to expire first.
+///
+/// # Errors
+/// Propogates any database error
+pub async fn get_active_surveys(ctx: &Context, waypoint_symbol: &str) -> Result<Vec<Survey>, sqlx::Error> {
+    sqlx::query("DELETE FROM surveys WHERE expiration <= NOW()")
+        .execute(&ctx.db_pool)
+        .await?;
+
+    let rows: Vec<(String, String, Vec<String>, String, String)> = sqlx::query_as(
+        "SELECT signature, waypoint_symbol, deposits,
+            to_char(expiration AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS.MS\"Z\"'), size
+        FROM surveys WHERE waypoint_symbol = $1 ORDER BY expiration")
+        .bind(waypoint_symbol)
+        .fetch_all(&ctx.db_pool)
+        .await?;
+
+    Ok(rows.into_iter()
+        .map(|(signature, symbol, deposits, expiration, size)| Survey {
+            signature,
+            symbol,
+            deposits: deposits.into_iter().map(SurveyDeposit::new).collect(),
+            expiration,
+            size: serde_json::from_value(serde_json::Value::String(size)).unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// A row of the `waypoints` table.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct WaypointRow {
+    pub symbol: String,
+    #[sqlx(rename = "type")]
+    pub waypoint_type: String,
+    pub system_symbol: String,
+    pub x: i32,
+    pub y: i32,
+    pub is_marketplace: Option<bool>,
+    pub is_shipyard: Option<bool>,
+    /// Display names of the waypoint's traits. Not a column, so only filled when converted from a fetched waypoint.
+    #[sqlx(default)]
+    pub traits: Vec<String>,
+    /// Symbol of the faction the waypoint belongs to. Only filled when converted from a fetched waypoint.
+    #[sqlx(default)]
+    pub faction: Option<String>,
+    /// Symbols of the waypoints orbiting this one. Only filled when converted from a fetched waypoint.
+    #[sqlx(default)]
+    pub orbitals: Vec<String>,
+}
+
+impl From<&Waypoint> for WaypointRow {
+    fn from(waypoint: &Waypoint) -> Self {
+        let has_trait = |symbol: &str| waypoint.traits.iter().any(|waypoint_trait| trait_symbol_name(waypoint_trait) == symbol);
+        WaypointRow {
+            symbol: waypoint.symbol.clone(),
+            waypoint_type: waypoint.r#type.to_string(),
+            system_symbol: waypoint.system_symbol.clone(),
+            x: waypoint.x,
+            y: waypoint.y,
+            is_marketplace: Some(has_trait("MARKETPLACE")),
+            is_shipyard: Some(has_trait("SHIPYARD")),
+            traits: waypoint.traits.iter().map(|waypoint_trait| waypoint_trait.name.clone()).collect(),
+            faction: waypoint.faction.as_ref().map(|faction| faction.symbol.clone()),
+            orbitals: waypoint.orbitals.iter().map(|orbital| orbital.symbol.clone()).collect(),
+        }
+    }
+}
+
+impl From<Waypoint> for WaypointRow {
+    fn from(waypoint: Waypoint) -> Self {
+        WaypointRow::from(&waypoint)
+    }
+}
+
+/// A row of the `systems` table.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct SystemRow {
+    pub symbol: String,
+    pub sector_symbol: String,
+    #[sqlx(rename = "type")]
+    pub system_type: String,
+    pub x: i32,
+    pub y: i32,
+    pub factions: Vec<String>,
+    pub controlling_faction: Option<String>,
+}
+
+impl From<&System> for SystemRow {
+    fn from(system: &System) -> Self {
+        SystemRow {
+            symbol: system.symbol.clone(),
+            sector_symbol: system.sector_symbol.clone(),
+            system_type: system.r#type.to_string(),
+            x: system.x,
+            y: system.y,
+            factions: system.factions.iter().map(|faction| faction.symbol.clone()).collect(),
+            controlling_faction: system.factions.first().map(|faction| faction.symbol.clone()),
+        }
+    }
+}
+
+impl From<System> for SystemRow {
+    fn from(system: System) -> Self {
+        SystemRow::from(&system)
+    }
+}
+
+/// Get all waypoints in the same system as `center_symbol` within `radius` of it, closest first.
+/// The center waypoint itself is included.
+///
+/// # Errors
+/// Propogates any database error
+pub async fn get_economic_zone(ctx: &Context, center_symbol: &str, radius: f64) -> Result<Vec<WaypointRow>, sqlx::Error> {
+    let Some(center) = ctx.query_cache.waypoint(&ctx.db_pool, center_symbol).await? else {
+        return Ok(Vec::new());
+    };
+    let tree = ctx.query_cache.waypoint_tree(&ctx.db_pool, &center.system_symbol).await?;
+    Ok(tree
+        .map(|tree| tree.within(center.x, center.y, radius).into_iter().map(|(waypoint, _)| waypoint.clone()).collect())
+        .unwrap_or_default())
+}
+
+/// The name the API uses for an enum value, e.g. `MARKETPLACE`.
+/// Some generated enums have no `Display` impl, so this goes through their serde name.
+pub fn api_name<T: Serialize + Debug>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(String::from))
+        .unwrap_or_else(|| format!("{value:?}"))
+}
+
+/// The API name of a waypoint trait, e.g. `MARKETPLACE`.
+pub fn trait_symbol_name(waypoint_trait: &WaypointTrait) -> String {
+    api_name(&waypoint_trait.symbol)
+}
+
+/// The parts of a system symbol, e.g. `X1-DF55` is sector `X1`, system `DF55`.
+pub struct SystemSymbolParts {
+    pub sector: String,
+    pub system: String,
+}
+
+impl Display for SystemSymbolParts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Sector {}, System {}", self.sector, self.system)
+    }
+}
+
+/// The parts of a waypoint symbol, e.g. `X1-DF55-A1B` is sector `X1`, system `DF55`, waypoint `A1B`.
+pub struct WaypointSymbolParts {
+    pub sector: String,
+    pub system: String,
+    pub waypoint_id: String,
+}
+
+impl WaypointSymbolParts {
+    /// The full symbol of the system the waypoint is in, e.g. `X1-DF55`.
+    pub fn system_symbol(&self) -> String {
+        format!("{}-{}", self.sector, self.system)
+    }
+}
+
+impl Display for WaypointSymbolParts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Sector {}, System {}", self.sector, self.system)
+    }
+}
+
+/// Split a system symbol into its sector and system. Missing parts are left empty.
+pub fn decode_system_symbol(symbol: &str) -> SystemSymbolParts {
+    let (sector, system) = symbol.split_once('-').unwrap_or((symbol, ""));
+    SystemSymbolParts { sector: sector.to_string(), system: system.to_string() }
+}
+
+/// Split a waypoint symbol into its sector, system and waypoint ID. Missing parts are left empty.
+pub fn decode_waypoint_symbol(symbol: &str) -> WaypointSymbolParts {
+    let mut parts = symbol.splitn(3, '-').map(str::to_string);
+    WaypointSymbolParts {
+        sector: parts.next().unwrap_or_default(),
+        system: parts.next().unwrap_or_default(),
+        waypoint_id: parts.next().unwrap_or_default(),
+    }
+}
+
+/// Why a symbol couldn't be decoded.
+#[derive(Debug)]
+pub enum SymbolError {
+    NotAWaypoint { symbol: String },
+}
+
+impl Display for SymbolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::NotAWaypoint { symbol } => write!(f, "{symbol} is not a waypoint symbol (expected SECTOR-SYSTEM-WAYPOINT)"),
+        }
+    }
+}
+
+/// Get the symbol of the system a waypoint is in, e.g. `X1-DF55` for `X1-DF55-20250Z`.
+///
+/// # Errors
+/// Returns an error if `waypoint_symbol` doesn't have a sector, system and waypoint part
+pub fn system_symbol_from_waypoint_symbol(waypoint_symbol: &str) -> Result<String, SymbolError> {
+    let parts = decode_waypoint_symbol(waypoint_symbol);
+    if parts.sector.is_empty() || parts.system.is_empty() || parts.waypoint_id.is_empty() {
+        return Err(SymbolError::NotAWaypoint { symbol: waypoint_symbol.to_string() });
+    }
+    Ok(parts.system_symbol())
+}
+
+/// Get the straight-line distance between two waypoints, or `None` if either is not in the database.
+///
+/// # Errors
+/// Propogates any database error
+pub async fn get_waypoint_distance(ctx: &Context, from_symbol: &str, to_symbol: &str) -> Result<Option<f64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT SQRT(POWER(b.x - a.x, 2) + POWER(b.y - a.y, 2))
+                FROM waypoints a, waypoints b
+                WHERE a.symbol = $1 AND b.symbol = $2")
+        .bind(from_symbol)
+        .bind(to_symbol)
+        .fetch_optional(&ctx.db_pool)
+        .await
+}
+
+/// Get the `limit` waypoints with `trait_symbol` closest to `origin_symbol` in its system, with their distances, closest first.
+/// Only waypoints whose traits have been recorded in `waypoint_traits` are considered.
+///
+/// # Errors
+/// Returns `RowNotFound` if `origin_symbol` is not in the waypoints table, and propogates any other database error
+pub async fn find_nearest_waypoints_with_trait(ctx: &Context, origin_symbol: &str, trait_symbol: &str, limit: u32) -> Result<Vec<(String, f64)>, sqlx::Error> {
+    let origin = ctx.query_cache.waypoint(&ctx.db_pool, origin_symbol).await?.ok_or(sqlx::Error::RowNotFound)?;
+
+    sqlx::query_as("SELECT w.symbol, SQRT(POWER(w.x - $1, 2) + POWER(w.y - $2, 2)) AS distance
+                FROM waypoints w
+                JOIN waypoint_traits t ON t.waypoint_symbol = w.symbol
+                WHERE w.system_symbol = $3 AND t.trait_symbol = $4
+                ORDER BY distance
+                LIMIT $5")
+        .bind(origin.x)
+        .bind(origin.y)
+        .bind(origin.system_symbol)
+        .bind(trait_symbol)
+        .bind(i64::from(limit))
+        .fetch_all(&ctx.db_pool)
+        .await
+}
+
+/// Estimate what `ship`'s cargo would sell for, at the best recorded price for each good among markets within
+/// `max_sell_distance` of the ship in its system. Goods with no recorded price nearby count as worth nothing.
+///
+/// # Errors
+/// Propogates any database error
+pub async fn estimate_cargo_value(ctx: &Context, ship: &Ship, max_sell_distance: f64) -> Result<i64, sqlx::Error> {
+    let trade_symbols: Vec<&str> = ship.cargo.inventory.iter().map(|item| item.symbol.as_str()).collect();
+    if trade_symbols.is_empty() {
+        return Ok(0);
+    }
+    let best_prices: HashMap<String, i32> = sqlx::query_as::<_, (String, i32)>(
+        "SELECT m.trade_symbol, MAX(m.sell_price) FROM market_prices m
+        JOIN waypoints w ON w.symbol = m.waypoint_symbol
+        JOIN waypoints o ON o.symbol = $1 AND o.system_symbol = w.system_symbol
+        WHERE m.trade_symbol = ANY($2) AND SQRT(POWER(w.x - o.x, 2) + POWER(w.y - o.y, 2)) <= $3
+        GROUP BY m.trade_symbol")
+        .bind(&ship.nav.waypoint_symbol)
+        .bind(&trade_symbols)
+        .bind(max_sell_distance)
+        .fetch_all(&ctx.db_pool)
+        .await?
+        .into_iter()
+        .collect();
+
+    Ok(ship.cargo.inventory.iter()
+        .map(|item| i64::from(item.units) * i64::from(best_prices.get(&item.symbol).copied().unwrap_or_default()))
+        .sum())
+}
+
+/// Estimate the fuel a trip of `distance` costs in `flight_mode`.
+/// DRIFT always costs a single unit; every other mode costs at least one unit per trip.
+pub fn estimate_fuel_cost(distance: f64, flight_mode: ShipNavFlightMode) -> i32 {
+    #[allow(clippy::cast_possible_truncation)]
+    let distance = distance.round() as i32;
+    match flight_mode {
+        ShipNavFlightMode::Drift => 1,
+        ShipNavFlightMode::Burn => (2 * distance).max(1),
+        ShipNavFlightMode::Cruise | ShipNavFlightMode::Stealth => distance.max(1),
+    }
+}
+
+/// Progress on a single delivery term of a contract.
+#[derive(Debug)]
+pub struct DeliveryProgress {
+    pub trade_symbol: String,
+    pub destination_symbol: String,
+    pub units_fulfilled: i32,
+    pub units_required: i32,
+}
+
+impl DeliveryProgress {
+    /// Percentage of the required units already delivered.
+    pub fn percent(&self) -> f64 {
+        if self.units_required <= 0 {
+            return 100.0;
+        }
+        100.0 * f64::from(self.units_fulfilled) / f64::from(self.units_required)
+    }
+}
+
+/// Flattened view of a [`Contract`] for display.
+#[derive(Debug)]
+pub struct ContractSummary {
+    pub id: String,
+    pub faction: String,
+    pub type_name: String,
+    pub accepted: bool,
+    pub fulfilled: bool,
+    pub expiration: String,
+    pub deliveries_progress: Vec<DeliveryProgress>,
+    /// Total payment, on acceptance and on fulfillment.
+    pub reward: i64,
+}
+
+impl From<&Contract> for ContractSummary {
+    fn from(contract: &Contract) -> Self {
+        ContractSummary {
+            id: contract.id.clone(),
+            faction: contract.faction_symbol.clone(),
+            type_name: format!("{:?}", contract.r#type).to_uppercase(),
+            accepted: contract.accepted,
+            fulfilled: contract.fulfilled,
+            expiration: contract.expiration.clone(),
+            deliveries_progress: contract.terms.deliver.iter().flatten()
+                .map(|deliver| DeliveryProgress {
+                    trade_symbol: deliver.trade_symbol.clone(),
+                    destination_symbol: deliver.destination_symbol.clone(),
+                    units_fulfilled: deliver.units_fulfilled,
+                    units_required: deliver.units_required,
+                })
+                .collect(),
+            reward: i64::from(contract.terms.payment.on_accepted) + i64::from(contract.terms.payment.on_fulfilled),
+        }
+    }
+}
+
+/// Get the server status, including announcements and server reset dates.
+/// `spacedust` has no binding for this endpoint, so the request is made directly.
+///
+/// # Errors
+/// Propogates any request or deserialization error, or the error response from the server
+pub async fn get_status(ctx: &Context) -> Result<serde_json::Value, Error<()>> {
+    let response = ctx.configuration.client.get(&ctx.configuration.base_path).send().await?;
+    let status = response.status();
+    let content = response.text().await?;
+    if status.is_client_error() || status.is_server_error() {
+        return Err(Error::ResponseError(ResponseContent { status, content, entity: None }));
+    }
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// A good flowing from a waypoint that exports it to one that imports it.
+#[derive(Debug)]
+pub struct ProductionEdge {
+    pub trade_symbol: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// Directed graph of the marketplaces in a system, linked by the goods they trade.
+#[derive(Debug, Default)]
+pub struct ProductionGraph {
+    /// Marketplace waypoint symbols, sorted.
+    pub nodes: Vec<String>,
+    pub edges: Vec<ProductionEdge>,
+    /// Marketplaces whose market data could not be fetched.
+    pub unavailable: Vec<String>,
+}
+
+impl ProductionGraph {
+    /// Edges leaving `node`.
+    pub fn outgoing<'a>(&'a self, node: &'a str) -> impl Iterator<Item = &'a ProductionEdge> {
+        self.edges.iter().filter(move |edge| edge.from == node)
+    }
+
+    /// Edges arriving at `node`.
+    pub fn incoming<'a>(&'a self, node: &'a str) -> impl Iterator<Item = &'a ProductionEdge> {
+        self.edges.iter().filter(move |edge| edge.to == node)
+    }
+
+    /// Classify a node by the direction of its edges.
+    pub fn role(&self, node: &str) -> &'static str {
+        match (self.incoming(node).next().is_some(), self.outgoing(node).next().is_some()) {
+            (false, true) => "producer",
+            (true, false) => "consumer",
+            (true, true) => "processor",
+            (false, false) => "isolated",
+        }
+    }
+}
+
+/// Build the production graph of a system from the import and export lists of its marketplaces.
+/// Every exporter of a good gets an edge to every importer of it.
+///
+/// # Errors
+/// Propogates any error from `get_system_waypoints`. Errors fetching individual markets are
+/// recorded in [`ProductionGraph::unavailable`] instead.
+pub async fn build_production_graph(ctx: &Context, system_symbol: &str) -> Result<ProductionGraph, Error<GetSystemWaypointsError>> {
+    let waypoints = list_system_waypoints(ctx, system_symbol).await?;
+    let mut graph = ProductionGraph::default();
+    // Good -> (exporters, importers)
+    let mut flows: BTreeMap<String, (Vec<String>, Vec<String>)> = BTreeMap::new();
+
+    for waypoint in waypoints {
+        if WaypointRow::from(&waypoint).is_marketplace != Some(true) {
+            continue;
+        }
+        match get_market_cached(ctx, system_symbol, &waypoint.symbol).await {
+            Ok(market) => {
+                for good in &market.exports {
+                    flows.entry(good.symbol.to_string()).or_default().0.push(waypoint.symbol.clone());
+                }
+                for good in &market.imports {
+                    flows.entry(good.symbol.to_string()).or_default().1.push(waypoint.symbol.clone());
+                }
+                graph.nodes.push(waypoint.symbol);
+            }
+            Err(_) => graph.unavailable.push(waypoint.symbol),
+        }
+    }
+
+    for (trade_symbol, (exporters, importers)) in flows {
+        for from in &exporters {
+            for to in importers.iter().filter(|to| *to != from) {
+                graph.edges.push(ProductionEdge { trade_symbol: trade_symbol.clone(), from: from.clone(), to: to.clone() });
+            }
+        }
+    }
+    graph.nodes.sort();
+    Ok(graph)
+}
+
+/// A jump between two systems and its length.
+#[derive(Debug, sqlx::FromRow)]
+pub struct JumpGateConnection {
+    pub from_system: String,
+    pub to_system: String,
+    pub distance: i32,
+}
+
+/// Get every known jump gate connection.
+///
+/// # Errors
+/// Propogates any database error
+pub async fn get_jump_gate_connections(ctx: &Context) -> Result<Vec<JumpGateConnection>, sqlx::Error> {
+    sqlx::query_as("SELECT from_system, to_system, distance FROM jump_gate_connections")
+        .fetch_all(&ctx.db_pool)
+        .await
+}
+
+/// Find the shortest route between two systems over jump gate connections, by total jump distance.
+/// Returns the systems visited in order, including both ends, or `None` if `to` can't be reached.
+pub fn shortest_jump_route(connections: &[JumpGateConnection], from: &str, to: &str) -> Option<Vec<String>> {
+    let mut adjacency: HashMap<&str, Vec<(&str, i64)>> = HashMap::new();
+    for connection in connections {
+        adjacency.entry(&connection.from_system).or_default().push((&connection.to_system, connection.distance.into()));
+    }
+
+    // Dijkstra, remembering how each system was first reached at its best distance.
+    let mut best: HashMap<&str, i64> = HashMap::from([(from, 0)]);
+    let mut previous: HashMap<&str, &str> = HashMap::new();
+    let mut queue = BinaryHeap::from([Reverse((0, from))]);
+    while let Some(Reverse((distance, system))) = queue.pop() {
+        if system == to {
+            let mut route = vec![to.to_string()];
+            let mut current = to;
+            while let Some(prev) = previous.get(current) {
+                route.push((*prev).to_string());
+                current = prev;
+            }
+            route.reverse();
+            return Some(route);
+        }
+        if best.get(system).is_some_and(|best| distance > *best) {
+            continue;
+        }
+        for &(next, length) in adjacency.get(system).into_iter().flatten() {
+            let next_distance = distance + length;
+            if best.get(next).is_none_or(|best| next_distance < *best) {
+                best.insert(next, next_distance);
+                previous.insert(next, system);
+                queue.push(Reverse((next_distance, next)));
+            }
+        }
+    }
+    None
+}
+
+/// Find every pair of systems connected by at most `max_hops` jumps, with the fewest jumps between them.
+/// Returns `(from, to, hops)` for each pair, not including a system paired with itself.
+pub fn jump_hop_counts(connections: &[JumpGateConnection], max_hops: i32) -> Vec<(String, String, i32)> {
+    let mut adjacency: HashMap<&str, Vec<&str>> = HashMap::new();
+    for connection in connections {
+        adjacency.entry(&connection.from_system).or_default().push(&connection.to_system);
+    }
+
+    let mut pairs = Vec::new();
+    for &from in adjacency.keys() {
+        // Breadth-first, so each system is first reached by its fewest jumps.
+        let mut seen = HashSet::from([from]);
+        let mut queue = VecDeque::from([(from, 0)]);
+        while let Some((system, hops)) = queue.pop_front() {
+            if hops == max_hops {
+                continue;
+            }
+            for &next in adjacency.get(system).into_iter().flatten() {
+                if seen.insert(next) {
+                    pairs.push((from.to_string(), next.to_string(), hops + 1));
+                    queue.push_back((next, hops + 1));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connections(pairs: &[(&str, &str)]) -> Vec<JumpGateConnection> {
+        pairs.iter()
+            .map(|(from, to)| JumpGateConnection { from_system: from.to_string(), to_system: to.to_string(), distance: 1 })
+            .collect()
+    }
+
+    fn sorted_hop_counts(connections: &[JumpGateConnection], max_hops: i32) -> Vec<(String, String, i32)> {
+        let mut pairs = jump_hop_counts(connections, max_hops);
+        pairs.sort();
+        pairs
+    }
+
+    fn hops(from: &str, to: &str, hops: i32) -> (String, String, i32) {
+        (from.to_string(), to.to_string(), hops)
+    }
+
+    #[test]
+    fn jump_hop_counts_stop_at_max_hops() {
+        let chain = connections(&[("A", "B"), ("B", "C"), ("C", "D")]);
+        assert_eq!(sorted_hop_counts(&chain, 2), vec![
+            hops("A", "B", 1), hops("A", "C", 2),
+            hops("B", "C", 1), hops("B", "D", 2),
+            hops("C", "D", 1),
+        ]);
+        assert!(jump_hop_counts(&chain, 0).is_empty());
+    }
+
+    #[test]
+    fn jump_hop_counts_use_fewest_hops_around_cycles() {
+        // A -> B -> C -> A, with a shortcut A -> C and C leading on to D.
+        let cycle = connections(&[("A", "B"), ("B", "C"), ("C", "A"), ("A", "C"), ("C", "D")]);
+        assert_eq!(sorted_hop_counts(&cycle, 10), vec![
+            hops("A", "B", 1), hops("A", "C", 1), hops("A", "D", 2),
+            hops("B", "A", 2), hops("B", "C", 1), hops("B", "D", 2),
+            hops("C", "A", 1), hops("C", "B", 2), hops("C", "D", 1),
+        ]);
+    }
+
+    #[test]
+    fn jump_hop_counts_never_pair_a_system_with_itself() {
+        let gates = connections(&[("A", "B"), ("B", "A"), ("A", "A")]);
+        let pairs = sorted_hop_counts(&gates, 10);
+        assert_eq!(pairs, vec![hops("A", "B", 1), hops("B", "A", 1)]);
+    }
+
+    #[test]
+    fn system_symbol_from_waypoint_symbol_needs_all_three_parts() {
+        assert_eq!(system_symbol_from_waypoint_symbol("X1-DF55-20250Z").unwrap(), "X1-DF55");
+        for symbol in ["X1-DF55", "X1", "", "X1--A"] {
+            assert!(
+                matches!(system_symbol_from_waypoint_symbol(symbol), Err(SymbolError::NotAWaypoint { symbol: ref s }) if s == symbol),
+                "{symbol:?} should not be a waypoint symbol"
+            );
+        }
+    }
+
+    #[test]
+    fn decode_waypoint_symbol_leaves_missing_parts_empty() {
+        let cases = [
+            ("X1-DF55-20250Z", ("X1", "DF55", "20250Z")),
+            ("X1-DF55", ("X1", "DF55", "")),
+            ("X1", ("X1", "", "")),
+            ("", ("", "", "")),
+            ("X1--A", ("X1", "", "A")),
+        ];
+        for (symbol, expected) in cases {
+            let parts = decode_waypoint_symbol(symbol);
+            assert_eq!((parts.sector.as_str(), parts.system.as_str(), parts.waypoint_id.as_str()), expected, "{symbol:?}");
+        }
+    }
+
+    #[test]
+    fn decode_system_symbol_leaves_missing_parts_empty() {
+        let cases = [
+            ("X1-DF55", ("X1", "DF55")),
+            ("X1-DF55-20250Z", ("X1", "DF55-20250Z")),
+            ("X1", ("X1", "")),
+            ("", ("", "")),
+            ("X1--A", ("X1", "-A")),
+        ];
+        for (symbol, expected) in cases {
+            let parts = decode_system_symbol(symbol);
+            assert_eq!((parts.sector.as_str(), parts.system.as_str()), expected, "{symbol:?}");
+        }
+    }
+}